@@ -0,0 +1,138 @@
+//! Accept rate of a single accept loop, as `Hopper::listen` runs by default,
+//! compared with one SO_REUSEPORT socket and accept loop per worker.
+//!
+//! Run with `cargo bench --bench accept`. Duration and number of
+//! connecting clients can be changed with `BENCH_SECS` and `BENCH_CLIENTS`
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+fn env(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn bind(address: SocketAddr, reuse_port: bool) -> TcpListener {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )
+    .unwrap();
+    socket.set_reuse_address(true).unwrap();
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(reuse_port).unwrap();
+    #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+    assert!(
+        !reuse_port,
+        "SO_REUSEPORT is not supported on this platform"
+    );
+
+    socket.bind(&address.into()).unwrap();
+    socket.listen(1024).unwrap();
+    socket.set_nonblocking(true).unwrap();
+
+    TcpListener::from_std(socket.into()).unwrap()
+}
+
+/// same loop as `Hopper::listen`, without handling the clients
+async fn accept(listener: TcpListener, accepted: Arc<AtomicUsize>) {
+    loop {
+        let (client, _) = listener.accept().await.unwrap();
+        accepted.fetch_add(1, Ordering::Relaxed);
+
+        tokio::spawn(async move { drop(client) });
+        tokio::task::yield_now().await
+    }
+}
+
+/// keeps opening and closing connections to `address` until `stop` is set
+async fn connect(address: SocketAddr, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        if let Ok(stream) = TcpStream::connect(address).await {
+            // resets the connection instead of leaving it in TIME_WAIT,
+            // which would quickly exhaust the ephemeral ports
+            stream.set_linger(Some(Duration::ZERO)).ok();
+        }
+    }
+}
+
+/// accepted connections per second with `acceptors` accept loops
+fn run(acceptors: usize, clients: u64, duration: Duration) -> f64 {
+    let server = tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .build()
+        .unwrap();
+    let client = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let reuse_port = acceptors > 1;
+
+    let address = server.block_on(async {
+        let first = bind("127.0.0.1:0".parse().unwrap(), reuse_port);
+        let address = first.local_addr().unwrap();
+
+        tokio::spawn(accept(first, accepted.clone()));
+        for _ in 1..acceptors {
+            tokio::spawn(accept(bind(address, true), accepted.clone()));
+        }
+
+        address
+    });
+
+    let start = Instant::now();
+    client.block_on(async {
+        let clients: Vec<_> = (0..clients)
+            .map(|_| tokio::spawn(connect(address, stop.clone())))
+            .collect();
+
+        tokio::time::sleep(duration).await;
+        stop.store(true, Ordering::Relaxed);
+
+        for client in clients {
+            client.await.unwrap();
+        }
+    });
+
+    let rate = accepted.load(Ordering::Relaxed) as f64 / start.elapsed().as_secs_f64();
+
+    client.shutdown_background();
+    server.shutdown_background();
+
+    rate
+}
+
+fn main() {
+    let duration = Duration::from_secs(env("BENCH_SECS", 5));
+    let clients = env("BENCH_CLIENTS", 64);
+    let workers = std::thread::available_parallelism().map_or(1, usize::from);
+
+    println!(
+        "{clients} clients, {}s per run, {workers} workers",
+        duration.as_secs()
+    );
+
+    let single = run(1, clients, duration);
+    println!("single accept loop: {single:>10.0} conn/s");
+
+    let reuse_port = run(workers, clients, duration);
+    println!(
+        "{workers} SO_REUSEPORT loops: {reuse_port:>10.0} conn/s ({:+.1}%)",
+        (reuse_port / single - 1.0) * 100.0
+    );
+}
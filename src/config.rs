@@ -1,44 +1,243 @@
-use self::{metrics::MetricsConfig, router::RouterConfig};
+use self::{
+    authentication::AuthenticationConfig,
+    forwarding::ForwardingConfig,
+    listener::ListenerConfig,
+    metrics::MetricsConfig,
+    router::{MergeError, RouterConfig, RouterFragment},
+};
+use crate::server::socket::SocketOptions;
 use config::{ConfigError, Environment, File, FileFormat};
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
+pub(super) mod authentication;
+pub(super) mod forwarding;
+pub(super) mod listener;
 pub(super) mod metrics;
 pub(super) mod router;
+pub(super) mod watcher;
+
+pub const CONFIG_FILE: &str = "Config.toml";
 
 #[derive(Error, Debug)]
-#[error("{0}. Have you created a Config.toml?")]
-pub struct ServerConfigError(#[from] ConfigError);
+pub enum ServerConfigError {
+    #[error("{0}. Have you created a Config.toml?")]
+    Config(#[from] ConfigError),
+
+    #[error("invalid include pattern: {0}")]
+    Include(#[from] glob::PatternError),
+
+    #[error("no listener configured, set listen or add a [[listeners]] entry")]
+    NoListener,
+
+    #[error("{0} is used by more than one listener")]
+    DuplicateListener(SocketAddr),
+
+    #[error("{0} enables proxy-protocol without trusted-proxies allowed to send the header")]
+    UntrustedProxies(SocketAddr),
+
+    #[error("{0}")]
+    Route(#[from] MergeError),
+
+    #[error("cannot read {}: {1}", .0.display())]
+    Read(PathBuf, std::io::Error),
+
+    #[error("invalid profiles in {}: {1}", .0.display())]
+    Profiles(PathBuf, serde_json::Error),
+}
 
 #[derive(Deserialize, Debug)]
 /// Defines the structure of a config file. Extension can be
 pub struct ServerConfig {
-    /// listening address
-    pub listen: SocketAddr,
+    /// listening address, shorthand for a
+    /// listener using the global routing table
+    pub listen: Option<SocketAddr>,
+
+    /// additional listeners, each with its own settings
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+
+    /// glob pattern of additional files containing
+    /// routes, e.g. `routes.d/*.toml`
+    pub include: Option<String>,
 
-    // pub routing: Option<RouterConfig>,
-    /// routing configuration
-    /// required because no other method is currently supported
+    /// routing configuration, shared by
+    /// listeners without their own table
+    #[serde(default)]
     pub routing: RouterConfig,
 
     pub metrics: Option<MetricsConfig>,
+
+    /// connections handled at once across every listener,
+    /// the ones above are closed as soon as they're accepted
+    #[serde(alias = "max-connections")]
+    pub max_connections: Option<usize>,
+
+    /// socket options of every listener, which can override them
+    #[serde(default)]
+    pub socket: SocketOptions,
+
+    /// secrets and profiles of the forwarding strategies
+    #[serde(default)]
+    pub forwarding: ForwardingConfig,
+
+    /// session server of the online-mode routes
+    #[serde(default)]
+    pub authentication: AuthenticationConfig,
+
+    #[serde(default)]
+    pub watch: WatchConfig,
+
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+}
+
+fn default_drain_timeout() -> u64 {
+    30
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShutdownConfig {
+    /// seconds to wait for open connections to close
+    #[serde(alias = "drain-timeout", default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+
+    /// when set, connections keep being accepted while draining and
+    /// get answered with this message instead of being routed
+    pub message: Option<String>,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: default_drain_timeout(),
+            message: None,
+        }
+    }
+}
+
+fn default_debounce() -> u64 {
+    250
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WatchConfig {
+    /// reload as soon as Config.toml changes
+    #[serde(default)]
+    pub config: bool,
+
+    /// milliseconds to wait for the changes to settle before reloading
+    #[serde(default = "default_debounce")]
+    pub debounce: u64,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            config: false,
+            debounce: default_debounce(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// Structure of an included file, which shares
+/// the `[routing]` layout of Config.toml
+struct IncludedFile {
+    #[serde(default)]
+    routing: RouterFragment,
+}
+
+impl IncludedFile {
+    fn read(path: &Path) -> Result<Self, ConfigError> {
+        config::Config::builder()
+            .add_source(File::from(path).format(FileFormat::Toml))
+            .build()?
+            .try_deserialize()
+    }
 }
 
 impl ServerConfig {
     /// reads configuration from Config.toml
     /// (more file exts can be supported through config's features)
     pub fn read() -> Result<Self, ServerConfigError> {
-        config::Config::builder()
+        let mut config: Self = config::Config::builder()
             .add_source(
                 Environment::default()
                     .prefix("HOPPER")
                     .separator("_")
                     .try_parsing(true),
             )
-            .add_source(File::new("Config.toml", FileFormat::Toml).required(false))
+            .add_source(File::new(CONFIG_FILE, FileFormat::Toml).required(false))
             .build()?
-            .try_deserialize()
-            .map_err(Into::into)
+            .try_deserialize()?;
+
+        if let Some(listen) = config.listen {
+            config.listeners.insert(0, ListenerConfig::new(listen));
+        }
+
+        for listener in &mut config.listeners {
+            listener.socket = listener.socket.or(config.socket);
+        }
+
+        if config.listeners.is_empty() {
+            return Err(ServerConfigError::NoListener);
+        }
+
+        let mut addresses = HashSet::new();
+        if let Some(listener) = config
+            .listeners
+            .iter()
+            .find(|listener| !addresses.insert(listener.listen))
+        {
+            return Err(ServerConfigError::DuplicateListener(listener.listen));
+        }
+
+        // headers would let anyone reaching the listener spoof their address
+        if let Some(listener) = config
+            .listeners
+            .iter()
+            .find(|listener| listener.proxy_protocol && listener.trusted_proxies.is_empty())
+        {
+            return Err(ServerConfigError::UntrustedProxies(listener.listen));
+        }
+
+        config.routing.validate()?;
+        for routing in config.listeners.iter().flat_map(|l| &l.routing) {
+            routing.validate()?;
+        }
+
+        if let Some(ref pattern) = config.include {
+            for path in glob::glob(pattern)? {
+                let path = match path {
+                    Ok(path) => path,
+                    Err(err) => {
+                        log::error!("Skipping included file: {err}");
+                        continue;
+                    }
+                };
+
+                // a broken file must not prevent the others from loading
+                let merged = IncludedFile::read(&path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|file| {
+                        config
+                            .routing
+                            .merge(file.routing)
+                            .map_err(|err| err.to_string())
+                    });
+
+                if let Err(err) = merged {
+                    log::error!("Skipping included file {}: {err}", path.display());
+                }
+            }
+        }
+
+        Ok(config)
     }
 }
@@ -0,0 +1,29 @@
+use serde::Deserialize;
+
+use crate::protocol::login::{SessionServer, MOJANG_SESSION_SERVER};
+
+fn default_session_server() -> String {
+    MOJANG_SESSION_SERVER.into()
+}
+
+#[derive(Deserialize, Debug)]
+/// Online-mode logins performed by hopper on the routes enabling them
+pub struct AuthenticationConfig {
+    /// base URL of the session server players are checked with
+    #[serde(alias = "session-server", default = "default_session_server")]
+    pub session_server: String,
+}
+
+impl Default for AuthenticationConfig {
+    fn default() -> Self {
+        Self {
+            session_server: default_session_server(),
+        }
+    }
+}
+
+impl AuthenticationConfig {
+    pub fn session_server(&self) -> SessionServer {
+        SessionServer::new(self.session_server.clone())
+    }
+}
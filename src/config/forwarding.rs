@@ -0,0 +1,56 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::server::bridge::{forwarding::ForwardingOptions, profiles::ProfileStore};
+
+use super::ServerConfigError;
+
+#[derive(Deserialize, Debug, Default)]
+/// Secrets shared with the backends, used by the forwarding
+/// strategies authenticating hopper, and forwarded profiles
+pub struct ForwardingConfig {
+    /// `forwarding-secret` of the velocity configuration of the backends
+    #[serde(alias = "velocity-secret")]
+    pub velocity_secret: Option<String>,
+
+    /// file containing the velocity secret, read on every (re)load
+    #[serde(alias = "velocity-secret-file")]
+    pub velocity_secret_file: Option<PathBuf>,
+
+    /// JSON file mapping UUIDs or usernames to the profile
+    /// properties forwarded by bungeecord forwarding
+    pub profiles: Option<PathBuf>,
+}
+
+impl ForwardingConfig {
+    /// reads the secrets and profiles configured in files
+    pub fn load(&self) -> Result<ForwardingOptions, ServerConfigError> {
+        let velocity = match (&self.velocity_secret, &self.velocity_secret_file) {
+            (Some(secret), _) => Some(secret.trim().to_string()),
+            (None, Some(path)) => {
+                let secret = fs::read_to_string(path)
+                    .map_err(|err| ServerConfigError::Read(path.clone(), err))?;
+                Some(secret.trim().to_string())
+            }
+            (None, None) => None,
+        };
+
+        let profiles = match self.profiles {
+            Some(ref path) => {
+                let profiles = fs::read_to_string(path)
+                    .map_err(|err| ServerConfigError::Read(path.clone(), err))?;
+                serde_json::from_str(&profiles)
+                    .map_err(|err| ServerConfigError::Profiles(path.clone(), err))?
+            }
+            None => ProfileStore::default(),
+        };
+
+        Ok(ForwardingOptions {
+            velocity: velocity
+                .filter(|secret| !secret.is_empty())
+                .map(|secret| Arc::from(secret.into_bytes())),
+            profiles,
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use std::net::SocketAddr;
+
+use serde::Deserialize;
+
+use crate::server::{bridge::forwarding::ForwardStrategy, cidr::Cidr, socket::SocketOptions};
+
+use super::router::RouterConfig;
+
+fn default_backlog() -> i32 {
+    1024
+}
+
+#[derive(Deserialize, Debug)]
+/// A socket hopper listens on, declared with `[[listeners]]`
+pub struct ListenerConfig {
+    /// listening address
+    pub listen: SocketAddr,
+
+    /// routing table of this listener,
+    /// the global `[routing]` is used when missing
+    pub routing: Option<RouterConfig>,
+
+    /// forwarding strategy of the routes which don't specify one
+    #[serde(alias = "ip-forwarding", default)]
+    pub ip_forwarding: Option<ForwardStrategy>,
+
+    /// clients connect through a load balancer which
+    /// sends a PROXY protocol header before the handshake
+    #[serde(alias = "proxy-protocol", default)]
+    pub proxy_protocol: bool,
+
+    /// addresses the PROXY protocol header is accepted from,
+    /// connections from any other address are refused
+    #[serde(alias = "trusted-proxies", default)]
+    pub trusted_proxies: Vec<Cidr>,
+
+    /// binds a socket per runtime worker with SO_REUSEPORT,
+    /// each with its own accept loop
+    #[serde(alias = "reuse-port", default)]
+    pub reuse_port: bool,
+
+    /// maximum number of connections waiting to be accepted
+    #[serde(default = "default_backlog")]
+    pub backlog: i32,
+
+    /// options of the accepted sockets, falling back to the global `[socket]`
+    #[serde(default)]
+    pub socket: SocketOptions,
+}
+
+impl ListenerConfig {
+    /// plain listener using the global routing table
+    pub fn new(listen: SocketAddr) -> Self {
+        Self {
+            listen,
+            routing: None,
+            ip_forwarding: None,
+            proxy_protocol: false,
+            trusted_proxies: Vec::new(),
+            reuse_port: false,
+            backlog: default_backlog(),
+            socket: SocketOptions::default(),
+        }
+    }
+
+    /// number of sockets, and accept loops, of this listener
+    pub fn acceptors(&self) -> usize {
+        match self.reuse_port {
+            // same as the default number of tokio workers
+            true => std::thread::available_parallelism().map_or(1, usize::from),
+            false => 1,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use serde::Deserialize;
 
 use crate::metrics::{influx::InfluxInjector, injector::MetricsInjector};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "type")]
 pub enum MetricsConfig {
     #[serde(rename = "influx")]
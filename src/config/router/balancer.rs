@@ -1,9 +1,8 @@
-use serde::Deserialize;
-use std::net::SocketAddr;
-
 use super::resolver::ResolvableAddr;
+use crate::server::backend::BackendAddr;
+use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, PartialEq)]
 #[serde(transparent)]
 pub struct Balanced {
     servers: Vec<ResolvableAddr>,
@@ -22,7 +21,32 @@ pub struct Balanced {
 // }
 
 impl Balanced {
-    pub(super) fn get(&self, n: usize) -> SocketAddr {
-        self.servers[n & self.servers.len()].into()
+    pub(super) fn get(&self, n: usize, proxied: bool) -> Option<BackendAddr> {
+        self.servers[n % self.servers.len()].get(proxied)
+    }
+
+    /// first hostname which couldn't be resolved when loading the configuration
+    pub(super) fn unresolved(&self) -> Option<&BackendAddr> {
+        self.servers.iter().find_map(ResolvableAddr::unresolved)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Balanced;
+    use crate::server::backend::BackendAddr;
+
+    #[test]
+    fn round_robin() {
+        let balanced: Balanced =
+            serde_json::from_str(r#"["127.0.0.1:25001", "127.0.0.1:25002", "127.0.0.1:25003"]"#)
+                .unwrap();
+
+        let picked: Vec<_> = (0..6).map(|n| balanced.get(n, false)).collect();
+        for (n, addr) in picked.into_iter().enumerate() {
+            let port = 25001 + n % 3;
+            let expected = format!("127.0.0.1:{port}").parse().unwrap();
+            assert_eq!(addr, Some(BackendAddr::Inet(expected)));
+        }
     }
 }
@@ -1,27 +1,77 @@
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::{net::ToSocketAddrs, sync::Arc};
 
-use serde::{Deserialize, Deserializer};
+use serde::Deserialize;
 
-#[derive(Deserialize, Debug, Clone, Copy)]
-pub(super) struct ResolvableAddr(#[serde(deserialize_with = "resolve_hostname")] SocketAddr);
+use crate::server::backend::BackendAddr;
 
-fn resolve_hostname<'de, D>(deserializer: D) -> Result<SocketAddr, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    use serde::de::Error;
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+/// Backend address, either an address, a hostname resolved when
+/// loading the configuration, or the path of a unix socket
+pub(super) struct ResolvableAddr {
+    /// None when the hostname can't be resolved locally
+    resolved: Option<BackendAddr>,
+    /// hostname as written, left for outbound proxies to resolve
+    named: Option<BackendAddr>,
+}
+
+impl TryFrom<String> for ResolvableAddr {
+    type Error = String;
+
+    fn try_from(inner: String) -> Result<Self, Self::Error> {
+        if let Some(path) = inner.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Self {
+                resolved: Some(BackendAddr::Unix(std::path::Path::new(path).into())),
+                named: None,
+            });
+
+            #[cfg(not(unix))]
+            return Err(format!(
+                "unix sockets are not supported on this platform: {path}"
+            ));
+        }
 
-    let inner = <String>::deserialize(deserializer)?;
+        if let Ok(address) = inner.parse() {
+            return Ok(Self {
+                resolved: Some(BackendAddr::Inet(address)),
+                named: None,
+            });
+        }
 
-    let mut addr = inner
-        .to_socket_addrs()
-        .map_err(|err| Error::custom(format!("invalid hostname format: {err}")))?;
+        let (host, port) = inner
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .filter(|(host, _)| !host.is_empty())
+            .ok_or_else(|| format!("invalid hostname format: {inner}"))?;
 
-    addr.next().ok_or_else(|| Error::missing_field("address"))
+        let resolved = (host, port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next());
+
+        Ok(Self {
+            resolved: resolved.map(BackendAddr::Inet),
+            named: Some(BackendAddr::Named {
+                host: Arc::from(host),
+                port,
+            }),
+        })
+    }
 }
 
-impl From<ResolvableAddr> for SocketAddr {
-    fn from(addr: ResolvableAddr) -> Self {
-        addr.0
+impl ResolvableAddr {
+    /// address connected to, the hostname as written when it is
+    /// `proxied` so that the proxy resolves it on its side
+    pub(super) fn get(&self, proxied: bool) -> Option<BackendAddr> {
+        match proxied {
+            true => self.named.as_ref().or(self.resolved.as_ref()).cloned(),
+            false => self.resolved.clone(),
+        }
+    }
+
+    /// hostname which couldn't be resolved when loading the configuration
+    pub(super) fn unresolved(&self) -> Option<&BackendAddr> {
+        self.named.as_ref().filter(|_| self.resolved.is_none())
     }
 }
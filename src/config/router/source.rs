@@ -0,0 +1,87 @@
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Addresses {
+    Single(IpAddr),
+    List(Vec<IpAddr>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(from = "Addresses")]
+/// Local addresses backend connections of a route are made from,
+/// rotated to spread connections across them
+pub struct SourceAddresses {
+    addresses: Vec<IpAddr>,
+    next: AtomicUsize,
+}
+
+impl From<Addresses> for SourceAddresses {
+    fn from(addresses: Addresses) -> Self {
+        let addresses = match addresses {
+            Addresses::Single(address) => vec![address],
+            Addresses::List(addresses) => addresses,
+        };
+
+        Self {
+            addresses,
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl PartialEq for SourceAddresses {
+    fn eq(&self, other: &Self) -> bool {
+        self.addresses == other.addresses
+    }
+}
+
+impl SourceAddresses {
+    /// picks the next address of the same family as `destination`
+    pub(super) fn next(&self, destination: SocketAddr) -> Option<IpAddr> {
+        let candidates = || {
+            self.addresses
+                .iter()
+                .filter(move |address| address.is_ipv6() == destination.is_ipv6())
+        };
+
+        let count = candidates().count();
+        if count == 0 {
+            return None;
+        }
+
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        candidates().nth(n % count).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use super::{Addresses, SourceAddresses};
+
+    #[test]
+    fn rotate() {
+        let addresses = ["10.0.0.1", "::1", "10.0.0.2"];
+        let sources = SourceAddresses::from(Addresses::List(
+            addresses.iter().map(|a| a.parse().unwrap()).collect(),
+        ));
+
+        let v4: SocketAddr = "1.1.1.1:25565".parse().unwrap();
+        let picked: Vec<_> = (0..4).map(|_| sources.next(v4).unwrap()).collect();
+        assert_eq!(picked[0], picked[2]);
+        assert_ne!(picked[0], picked[1]);
+        assert!(picked.iter().all(|address| address.is_ipv4()));
+
+        let v6: SocketAddr = "[::2]:25565".parse().unwrap();
+        assert_eq!(sources.next(v6), Some("::1".parse().unwrap()));
+
+        let single = SourceAddresses::from(Addresses::Single("::1".parse().unwrap()));
+        assert_eq!(single.next(v4), None);
+    }
+}
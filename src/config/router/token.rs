@@ -0,0 +1,91 @@
+use serde::Deserialize;
+use std::{env::VarError, fmt::Debug, path::PathBuf, sync::Arc};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenSource {
+    Inline(String),
+    File { file: PathBuf },
+    Env { env: String },
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "TokenSource")]
+/// Secret written in the configuration, or read from
+/// `{ file = "path" }` or `{ env = "VARIABLE" }` when loaded
+pub struct Token(Arc<str>);
+
+impl TokenSource {
+    /// reads the token, environment variables being looked up with `var`
+    fn load(self, var: impl Fn(&str) -> Result<String, VarError>) -> Result<Token, String> {
+        let token = match self {
+            TokenSource::Inline(token) => token,
+            TokenSource::File { file } => std::fs::read_to_string(&file)
+                .map_err(|err| format!("cannot read token from {}: {err}", file.display()))?,
+            TokenSource::Env { env } => {
+                var(&env).map_err(|err| format!("cannot read token from ${env}: {err}"))?
+            }
+        };
+
+        match token.trim() {
+            "" => Err("the token is empty".into()),
+            token => Ok(Token(token.into())),
+        }
+    }
+}
+
+impl TryFrom<TokenSource> for Token {
+    type Error = String;
+
+    fn try_from(source: TokenSource) -> Result<Self, Self::Error> {
+        source.load(|var| std::env::var(var))
+    }
+}
+
+impl Token {
+    pub fn value(&self) -> &Arc<str> {
+        &self.0
+    }
+}
+
+// keeps the token out of the logs
+impl Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::VarError;
+
+    use super::TokenSource;
+
+    #[test]
+    fn sources() {
+        let var = |var: &str| match var {
+            "HOPPER_TEST_TOKEN" => Ok("from-env".to_string()),
+            _ => Err(VarError::NotPresent),
+        };
+        let token = |source: TokenSource| source.load(var).map(|token| token.0.to_string());
+
+        assert_eq!(token(TokenSource::Inline("abc\n".into())), Ok("abc".into()));
+
+        let env = TokenSource::Env {
+            env: "HOPPER_TEST_TOKEN".into(),
+        };
+        assert_eq!(token(env), Ok("from-env".into()));
+
+        let path = std::env::temp_dir().join(format!("hopper-test-token-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let file = token(TokenSource::File { file: path.clone() });
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(file, Ok("from-file".into()));
+
+        let missing = TokenSource::Env {
+            env: "HOPPER_TEST_MISSING".into(),
+        };
+        assert!(token(missing).is_err());
+        assert!(token(TokenSource::Inline(" ".into())).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Filesystem watcher signaling changes to configuration files
+
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use glob::Pattern;
+use notify::{event::EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use super::{ServerConfig, CONFIG_FILE};
+
+pub struct ConfigWatcher {
+    watcher: Option<RecommendedWatcher>,
+    sender: UnboundedSender<notify::Event>,
+    events: UnboundedReceiver<notify::Event>,
+
+    /// only changes to files matching these patterns are notified
+    patterns: Vec<Pattern>,
+
+    /// editors usually produce a burst of events for a single save,
+    /// which get coalesced into a single change notification
+    debounce: Duration,
+}
+
+/// splits a glob pattern into the directory it starts from
+/// and the remaining part containing the wildcards
+fn split_pattern(pattern: &str) -> (PathBuf, PathBuf) {
+    let is_glob = |component: &Component| {
+        component
+            .as_os_str()
+            .to_string_lossy()
+            .contains(['*', '?', '['])
+    };
+
+    let mut components = Path::new(pattern).components().peekable();
+    let mut base = PathBuf::new();
+
+    while let Some(component) = components.next_if(|c| !is_glob(c)) {
+        base.push(component);
+    }
+
+    let rest: PathBuf = components.collect();
+
+    // a pattern without wildcards watches the parent of a single file
+    match rest.as_os_str().is_empty() {
+        true => {
+            let file = base.file_name().map(PathBuf::from).unwrap_or_default();
+            base.pop();
+            (base, file)
+        }
+        false => (base, rest),
+    }
+}
+
+impl ConfigWatcher {
+    /// watches every file that makes up `config`
+    pub fn new(config: &ServerConfig) -> Self {
+        let (sender, events) = mpsc::unbounded_channel();
+
+        let mut watcher = Self {
+            watcher: None,
+            sender,
+            events,
+            patterns: Vec::new(),
+            debounce: Duration::from_millis(config.watch.debounce),
+        };
+
+        if config.watch.config {
+            watcher.watch(CONFIG_FILE);
+        }
+
+        if let Some(ref include) = config.include {
+            watcher.watch(include);
+        }
+
+        watcher
+    }
+
+    /// watches the files matching `pattern`, logging any error
+    fn watch(&mut self, pattern: &str) {
+        if let Err(err) = self.try_watch(pattern) {
+            log::error!("Cannot watch {pattern} for changes: {err}");
+        }
+    }
+
+    fn try_watch(&mut self, pattern: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let (base, rest) = split_pattern(pattern);
+
+        // notify reports absolute paths, patterns must be absolute too
+        let base = match base.as_os_str().is_empty() {
+            true => std::env::current_dir()?,
+            false => base.canonicalize()?,
+        };
+
+        let base_str = base.to_str().ok_or("path is not valid UTF-8")?;
+        let absolute = Path::new(&Pattern::escape(base_str)).join(&rest);
+        let absolute = absolute.to_str().ok_or("path is not valid UTF-8")?;
+
+        let recursive = match pattern.contains("**") {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+
+        let watcher = match self.watcher {
+            Some(ref mut watcher) => watcher,
+            None => {
+                let sender = self.sender.clone();
+                let watcher = notify::recommended_watcher(move |event| match event {
+                    // the receiver is dropped along with the watcher
+                    Ok(event) => {
+                        let _ = sender.send(event);
+                    }
+                    Err(err) => log::error!("Configuration watcher error: {err}"),
+                })?;
+
+                self.watcher.insert(watcher)
+            }
+        };
+
+        watcher.watch(&base, recursive)?;
+
+        // files bind mounted on their own (e.g. docker volumes) only
+        // notify their own inode, not the directory they're mounted in
+        let file = base.join(&rest);
+        if !pattern.contains(['*', '?', '[']) && file.is_file() {
+            watcher.watch(&file, RecursiveMode::NonRecursive)?;
+        }
+        self.patterns.push(Pattern::new(absolute)?);
+
+        Ok(())
+    }
+
+    fn is_relevant(&self, event: &notify::Event) -> bool {
+        !matches!(event.kind, EventKind::Access(_))
+            && event
+                .paths
+                .iter()
+                .any(|path| self.patterns.iter().any(|p| p.matches_path(path)))
+    }
+
+    /// resolves once a watched file has been created, modified or removed
+    pub async fn changed(&mut self) {
+        loop {
+            match self.events.recv().await {
+                Some(event) if self.is_relevant(&event) => break,
+                Some(_) => continue,
+                // the sender is owned by self, this cannot happen
+                None => unreachable!(),
+            }
+        }
+
+        while let Ok(Some(_)) = tokio::time::timeout(self.debounce, self.events.recv()).await {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::split_pattern;
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            split_pattern("routes.d/*.toml"),
+            (PathBuf::from("routes.d"), PathBuf::from("*.toml"))
+        );
+        assert_eq!(
+            split_pattern("/etc/hopper/**/*.toml"),
+            (PathBuf::from("/etc/hopper"), PathBuf::from("**/*.toml"))
+        );
+        assert_eq!(
+            split_pattern("Config.toml"),
+            (PathBuf::new(), PathBuf::from("Config.toml"))
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Service discovery providers that populate
+//! routes and pools at runtime
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use tokio::task::JoinHandle;
+
+use crate::server::bridge::forwarding::ForwardStrategy;
+
+pub mod consul;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Health of a discovered backend. Ordered from
+/// the most preferable to the least preferable
+pub enum Health {
+    Passing,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone)]
+/// A single backend instance reported by a discovery provider
+pub struct Instance {
+    pub address: SocketAddr,
+    pub health: Health,
+
+    /// hostnames this instance should be reachable from,
+    /// only used when discovering whole routes
+    pub hostnames: Vec<String>,
+    pub forwarding: Option<ForwardStrategy>,
+}
+
+/// picks an instance using `hash`, only considering the instances
+/// with the best health available. Critical instances are never picked.
+fn select(instances: &[Instance], hash: u64) -> Option<&Instance> {
+    let best = instances
+        .iter()
+        .map(|instance| instance.health)
+        .filter(|&health| health != Health::Critical)
+        .min()?;
+
+    let candidates: Vec<_> = instances
+        .iter()
+        .filter(|instance| instance.health == best)
+        .collect();
+
+    Some(candidates[hash as usize % candidates.len()])
+}
+
+#[derive(Debug, Default, Clone)]
+/// Backends of a single service, kept up to date
+/// by a discovery task running in the background
+pub struct ServicePool(Arc<RwLock<Vec<Instance>>>);
+
+impl ServicePool {
+    pub fn update(&self, instances: Vec<Instance>) {
+        *self.0.write().unwrap() = instances;
+    }
+
+    pub fn select(&self, hash: u64) -> Option<Instance> {
+        select(&self.0.read().unwrap(), hash).cloned()
+    }
+
+    /// copies the instances of `other`, until the next update
+    pub fn seed(&self, other: &ServicePool) {
+        self.update(other.0.read().unwrap().clone());
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct RoutesInner {
+    /// instances grouped by the service that reported them
+    services: HashMap<String, Vec<Instance>>,
+    /// same instances indexed by each of their hostnames
+    hostnames: HashMap<String, Vec<Instance>>,
+}
+
+#[derive(Debug, Default, Clone)]
+/// Whole routes discovered at runtime, indexed by hostname
+pub struct DiscoveredRoutes(Arc<RwLock<RoutesInner>>);
+
+impl DiscoveredRoutes {
+    /// replaces all the instances of `service`. Passing an
+    /// empty list removes the service altogether
+    pub fn update(&self, service: &str, instances: Vec<Instance>) {
+        let mut inner = self.0.write().unwrap();
+
+        match instances.is_empty() {
+            true => inner.services.remove(service),
+            false => inner.services.insert(service.to_string(), instances),
+        };
+
+        let mut hostnames: HashMap<String, Vec<Instance>> = HashMap::new();
+        for instance in inner.services.values().flatten() {
+            for hostname in &instance.hostnames {
+                hostnames
+                    .entry(hostname.clone())
+                    .or_default()
+                    .push(instance.clone());
+            }
+        }
+
+        inner.hostnames = hostnames;
+    }
+
+    pub fn select(&self, hostname: &str, hash: u64) -> Option<Instance> {
+        let inner = self.0.read().unwrap();
+        let instances = inner.hostnames.get(hostname)?;
+
+        select(instances, hash).cloned()
+    }
+
+    /// copies the routes of `other`, until the next updates
+    pub fn seed(&self, other: &DiscoveredRoutes) {
+        let routes = other.0.read().unwrap().clone();
+        *self.0.write().unwrap() = routes;
+    }
+}
+
+#[derive(Debug)]
+/// Aborts the wrapped task when dropped, so discovery
+/// tasks never outlive the router that spawned them
+pub struct TaskGuard(JoinHandle<()>);
+
+impl TaskGuard {
+    pub fn spawn(future: impl std::future::Future<Output = ()> + Send + 'static) -> Self {
+        Self(tokio::spawn(future))
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,499 @@
+//! Consul catalog provider. Uses blocking queries
+//! so catalog changes are picked up as soon as they happen
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{DiscoveredRoutes, Health, Instance, ServicePool, TaskGuard};
+use crate::server::bridge::forwarding::ForwardStrategy;
+
+/// metadata keys (or `key=value` tags) read from service entries
+const HOSTNAME_KEY: &str = "hopper-hostname";
+const FORWARDING_KEY: &str = "hopper-forwarding";
+
+/// time to wait before retrying a failed query
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum ConsulError {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("response is missing the X-Consul-Index header")]
+    Index,
+}
+
+fn default_address() -> String {
+    "http://127.0.0.1:8500".into()
+}
+
+fn default_tag() -> String {
+    "hopper".into()
+}
+
+fn default_wait() -> u64 {
+    300
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConsulConfig {
+    /// Consul HTTP API address
+    #[serde(default = "default_address")]
+    address: String,
+
+    /// ACL token sent along every request
+    token: Option<String>,
+    datacenter: Option<String>,
+
+    /// populate whole routes from services carrying `tag`
+    #[serde(alias = "discover-routes", default)]
+    discover_routes: bool,
+
+    #[serde(default = "default_tag")]
+    tag: String,
+
+    /// maximum duration of a blocking query, in seconds
+    #[serde(default = "default_wait")]
+    wait: u64,
+}
+
+impl Default for ConsulConfig {
+    fn default() -> Self {
+        Self {
+            address: default_address(),
+            token: None,
+            datacenter: None,
+            discover_routes: false,
+            tag: default_tag(),
+            wait: default_wait(),
+        }
+    }
+}
+
+impl ConsulConfig {
+    pub fn discover_routes(&self) -> bool {
+        self.discover_routes
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct NodeEntry {
+    address: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceEntry {
+    address: String,
+    port: u16,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    meta: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct CheckEntry {
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct HealthEntry {
+    node: NodeEntry,
+    service: ServiceEntry,
+    #[serde(default)]
+    checks: Vec<CheckEntry>,
+}
+
+impl ServiceEntry {
+    /// looks up `key` first in the service metadata and
+    /// then in tags formatted as `key=value`
+    fn lookup(&self, key: &str) -> Option<&str> {
+        let meta = self.meta.as_ref().and_then(|meta| meta.get(key));
+
+        let tag = || {
+            self.tags
+                .iter()
+                .flatten()
+                .filter_map(|tag| tag.split_once('='))
+                .find(|&(k, _)| k == key)
+                .map(|(_, value)| value)
+        };
+
+        meta.map(String::as_str).or_else(tag)
+    }
+}
+
+impl HealthEntry {
+    /// the worst check status determines the health of the instance
+    fn health(&self) -> Health {
+        self.checks
+            .iter()
+            .map(|check| match check.status.as_str() {
+                "passing" => Health::Passing,
+                "warning" => Health::Warning,
+                _ => Health::Critical,
+            })
+            .max()
+            .unwrap_or(Health::Passing)
+    }
+
+    async fn into_instance(self) -> Option<Instance> {
+        let health = self.health();
+        let service = self.service;
+
+        let hostnames = service
+            .lookup(HOSTNAME_KEY)
+            .map(|list| list.split(',').map(|s| s.trim().to_string()).collect())
+            .unwrap_or_default();
+
+        let forwarding = service
+            .lookup(FORWARDING_KEY)
+            .and_then(|strategy| serde_json::from_value::<ForwardStrategy>(strategy.into()).ok());
+
+        // the service address falls back to the node address when empty
+        let host = match service.address.is_empty() {
+            true => self.node.address,
+            false => service.address,
+        };
+
+        let address = match tokio::net::lookup_host((host.as_str(), service.port)).await {
+            Ok(mut addrs) => addrs.next()?,
+            Err(err) => {
+                log::error!("Cannot resolve consul service address {host}: {err}");
+                return None;
+            }
+        };
+
+        Some(Instance {
+            address,
+            health,
+            hostnames,
+            forwarding,
+        })
+    }
+}
+
+/// result of a blocking query
+struct Indexed<T> {
+    index: u64,
+    data: T,
+}
+
+pub struct ConsulClient {
+    http: reqwest::Client,
+    config: ConsulConfig,
+}
+
+impl ConsulClient {
+    pub fn new(config: ConsulConfig) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    async fn blocking_query<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        index: u64,
+    ) -> Result<Indexed<T>, ConsulError> {
+        let wait = format!("{}s", self.config.wait);
+        let index_param = index.to_string();
+
+        let mut request = self
+            .http
+            .get(format!(
+                "{}{path}",
+                self.config.address.trim_end_matches('/')
+            ))
+            .query(&[("index", index_param.as_str()), ("wait", wait.as_str())])
+            // consul adds up to wait/16 of jitter to blocking queries
+            .timeout(Duration::from_secs(
+                self.config.wait + self.config.wait / 16 + 10,
+            ));
+
+        if let Some(ref datacenter) = self.config.datacenter {
+            request = request.query(&[("dc", datacenter)]);
+        }
+
+        if let Some(ref token) = self.config.token {
+            request = request.header("X-Consul-Token", token);
+        }
+
+        let response = request.send().await?.error_for_status()?;
+
+        let index = response
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or(ConsulError::Index)?;
+
+        let data = response.json().await?;
+        Ok(Indexed { index, data })
+    }
+
+    async fn service_instances(
+        &self,
+        service: &str,
+        index: u64,
+    ) -> Result<Indexed<Vec<Instance>>, ConsulError> {
+        let Indexed { index, data } = self
+            .blocking_query::<Vec<HealthEntry>>(&format!("/v1/health/service/{service}"), index)
+            .await?;
+
+        let mut instances = Vec::with_capacity(data.len());
+        for entry in data {
+            instances.extend(entry.into_instance().await);
+        }
+
+        Ok(Indexed {
+            index,
+            data: instances,
+        })
+    }
+
+    /// names of the services carrying the configured tag
+    async fn tagged_services(&self, index: u64) -> Result<Indexed<Vec<String>>, ConsulError> {
+        let Indexed { index, data } = self
+            .blocking_query::<HashMap<String, Vec<String>>>("/v1/catalog/services", index)
+            .await?;
+
+        let services = data
+            .into_iter()
+            .filter(|(_, tags)| tags.contains(&self.config.tag))
+            .map(|(name, _)| name)
+            .collect();
+
+        Ok(Indexed {
+            index,
+            data: services,
+        })
+    }
+
+    /// watches the instances of `service`, handing every
+    /// change over to `update`
+    async fn watch(&self, service: &str, mut update: impl FnMut(Vec<Instance>)) -> ! {
+        let mut index = 0;
+
+        loop {
+            match self.service_instances(service, index).await {
+                Ok(result) => {
+                    // consul indexes may go backwards, in which case
+                    // the watch needs to be restarted from scratch
+                    index = match result.index < index {
+                        true => 0,
+                        false => result.index,
+                    };
+
+                    update(result.data)
+                }
+                Err(err) => {
+                    log::error!("Consul query for service {service} failed: {err}");
+                    index = 0;
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// keeps `pool` in sync with the healthy instances of `service`
+    pub async fn watch_pool(self: Arc<Self>, service: String, pool: ServicePool) {
+        self.watch(&service, |instances| pool.update(instances))
+            .await
+    }
+
+    /// keeps `routes` in sync with every service carrying the configured tag
+    pub async fn watch_routes(self: Arc<Self>, routes: DiscoveredRoutes) {
+        // dropping a guard stops the watch of the corresponding service
+        let mut watchers: HashMap<String, TaskGuard> = HashMap::new();
+        let mut index = 0;
+
+        loop {
+            let result = match self.tagged_services(index).await {
+                Ok(result) => result,
+                Err(err) => {
+                    log::error!("Consul catalog query failed: {err}");
+                    index = 0;
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+
+            index = match result.index < index {
+                true => 0,
+                false => result.index,
+            };
+
+            watchers.retain(|service, _| {
+                let keep = result.data.contains(service);
+                if !keep {
+                    routes.update(service, Vec::new());
+                }
+
+                keep
+            });
+
+            for service in result.data {
+                if watchers.contains_key(&service) {
+                    continue;
+                }
+
+                log::info!("Discovered consul service {service}");
+
+                let client = self.clone();
+                let routes = routes.clone();
+                let name = service.clone();
+
+                let watcher = TaskGuard::spawn(async move {
+                    client
+                        .watch(&name, |instances| routes.update(&name, instances))
+                        .await
+                });
+
+                watchers.insert(service, watcher);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{ConsulClient, ConsulConfig};
+    use crate::{
+        discovery::{DiscoveredRoutes, ServicePool},
+        server::bridge::forwarding::ForwardStrategy,
+    };
+
+    const CATALOG: &str = r#"{"lobby":["hopper"],"database":[]}"#;
+
+    const LOBBY: &str = r#"[
+        {
+            "Node": { "Address": "127.0.0.2" },
+            "Service": {
+                "Address": "",
+                "Port": 25001,
+                "Tags": ["hopper", "hopper-forwarding=bungeecord"],
+                "Meta": { "hopper-hostname": "mc.example.com, play.example.com" }
+            },
+            "Checks": [{ "Status": "passing" }]
+        },
+        {
+            "Node": { "Address": "127.0.0.2" },
+            "Service": { "Address": "127.0.0.3", "Port": 25002, "Tags": null, "Meta": null },
+            "Checks": [{ "Status": "passing" }, { "Status": "critical" }]
+        }
+    ]"#;
+
+    /// answers the first query of every endpoint and then
+    /// keeps any subsequent blocking query hanging
+    async fn respond(mut stream: TcpStream) {
+        let mut request = Vec::new();
+
+        loop {
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            if read == 0 {
+                return;
+            }
+
+            request.extend_from_slice(&buf[..read]);
+            if !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                continue;
+            }
+
+            let head = String::from_utf8(std::mem::take(&mut request)).unwrap();
+            let path = head.split(' ').nth(1).unwrap();
+
+            if !path.contains("index=0") {
+                std::future::pending::<()>().await;
+            }
+
+            let body = match path {
+                p if p.starts_with("/v1/catalog/services") => CATALOG,
+                p if p.starts_with("/v1/health/service/lobby") => LOBBY,
+                _ => "[]",
+            };
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nX-Consul-Index: 10\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    async fn stand_in() -> Arc<ConsulClient> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(respond(stream));
+            }
+        });
+
+        Arc::new(ConsulClient::new(ConsulConfig {
+            address: format!("http://{address}"),
+            ..Default::default()
+        }))
+    }
+
+    async fn eventually(mut condition: impl FnMut() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("condition was never met")
+    }
+
+    #[tokio::test]
+    async fn pool_skips_critical() {
+        let client = stand_in().await;
+        let pool = ServicePool::default();
+
+        tokio::spawn(client.watch_pool("lobby".into(), pool.clone()));
+        eventually(|| pool.select(0).is_some()).await;
+
+        // the second instance is critical and must never be selected
+        for hash in 0..4 {
+            let instance = pool.select(hash).unwrap();
+            assert_eq!(instance.address, "127.0.0.2:25001".parse().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn discover_routes() {
+        let client = stand_in().await;
+        let routes = DiscoveredRoutes::default();
+
+        tokio::spawn(client.watch_routes(routes.clone()));
+        eventually(|| routes.select("mc.example.com", 0).is_some()).await;
+
+        let instance = routes.select("play.example.com", 0).unwrap();
+        assert_eq!(instance.address, "127.0.0.2:25001".parse().unwrap());
+        assert!(matches!(
+            instance.forwarding,
+            Some(ForwardStrategy::BungeeCord)
+        ));
+
+        assert!(routes.select("database", 0).is_none());
+    }
+}
@@ -2,7 +2,7 @@ use thiserror::Error;
 
 use crate::{
     config::ServerConfigError,
-    protocol::{connection::ConnectionError, packet::ProtoError},
+    protocol::{connection::ConnectionError, login::LoginError, packet::ProtoError},
     server::router::RouterError,
 };
 
@@ -23,18 +23,36 @@ pub enum HopperError {
     #[error("unable to connect to backend server: {0}")]
     Connect(std::io::Error),
 
+    #[error("cannot set socket options: {0}")]
+    SocketOptions(std::io::Error),
+
     #[error("one of the two parties took too long to respond")]
     TimeOut,
 
     #[error("the user sent invalid handshake data")]
     Invalid,
 
+    #[error("{0} is not a trusted proxy, refusing its PROXY protocol header")]
+    Untrusted(std::net::SocketAddr),
+
+    #[error("online-mode login failed: {0}")]
+    Login(#[from] LoginError),
+
+    #[error("{0} forwarding requires a secret in the [forwarding] section")]
+    NoSecret(&'static str),
+
     #[error("configuration error: {0}")]
     Config(#[from] ServerConfigError),
 
     #[error("cannot listen on the specified ip: {0}")]
     Bind(std::io::Error),
 
-    #[error("received sigint signal")]
+    #[error("cannot take over the listener of the previous process: {0}")]
+    Upgrade(std::io::Error),
+
+    #[error("cannot use the socket passed by systemd: {0}")]
+    Activation(std::io::Error),
+
+    #[error("received a second termination signal, open connections have been dropped")]
     Signal,
 }
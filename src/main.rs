@@ -1,31 +1,354 @@
-use std::{convert::Infallible, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 
-use crate::config::{metrics::MetricsConfig, ServerConfig};
+use crate::config::{
+    listener::ListenerConfig, metrics::MetricsConfig, router::RouterConfig, watcher::ConfigWatcher,
+    ServerConfig,
+};
+use discovery::TaskGuard;
 use log::LevelFilter;
 use metrics::injector::EmptyInjector;
-use server::Hopper;
+use server::{Hopper, Listener, Router, SwappableRouter};
 use simple_logger::SimpleLogger;
 use tokio::{net::TcpListener, select};
 
 pub use crate::error::HopperError;
 
 mod config;
+mod discovery;
 pub mod error;
 pub mod metrics;
 pub mod protocol;
 mod server;
+#[cfg(unix)]
+mod systemd;
+#[cfg(unix)]
+mod upgrade;
 
 // #[allow(clippy::uninit_vec, unused_macros)]
 // pub mod protocol;
 
-// only returns a configuration if it's valid
 #[cfg(target_os = "linux")]
-async fn reload_valid() -> ServerConfig {
+async fn hangup() {
     let mut signal =
         tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).unwrap();
 
+    signal.recv().await;
+}
+
+#[cfg(not(target_os = "linux"))]
+fn hangup() -> impl futures::Future<Output = ()> {
+    futures::future::pending()
+}
+
+#[cfg(unix)]
+async fn terminate() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut signal = signal(SignalKind::terminate()).unwrap();
+
+    select! {
+        _ = signal.recv() => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminate() {
+    tokio::signal::ctrl_c().await.unwrap();
+}
+
+/// Upgrades asked for with SIGUSR2. The listeners are handed over to the
+/// new process by a blocking task owning duplicates of their sockets, which
+/// keeps going even when a reload closes them in the meantime
+#[cfg(unix)]
+struct Upgrader {
+    signal: tokio::signal::unix::Signal,
+    handoff: Option<tokio::task::JoinHandle<std::io::Result<()>>>,
+}
+
+#[cfg(unix)]
+impl Upgrader {
+    fn new() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        Self {
+            signal: signal(SignalKind::user_defined2()).unwrap(),
+            handoff: None,
+        }
+    }
+
+    /// starts handing `bound` over on SIGUSR2, resolving once the
+    /// handoff is over. When it succeeds the new process is serving
+    async fn upgrade(&mut self, bound: &HashMap<SocketAddr, Bound>) -> std::io::Result<()> {
+        use std::os::fd::AsFd;
+
+        loop {
+            if let Some(ref mut handoff) = self.handoff {
+                let result = handoff.await.unwrap_or_else(|err| Err(err.into()));
+                self.handoff = None;
+                return result;
+            }
+
+            self.signal.recv().await;
+            log::info!("Upgrading, handing the listeners over to a new process...");
+
+            let listeners = bound
+                .values()
+                .flat_map(|bound| &bound.sockets)
+                .map(|socket| socket.as_fd().try_clone_to_owned())
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let handoff = tokio::task::spawn_blocking(move || upgrade::handoff(&listeners));
+            self.handoff = Some(handoff);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Upgrader;
+
+#[cfg(not(unix))]
+impl Upgrader {
+    fn new() -> Self {
+        Self
+    }
+
+    async fn upgrade(&mut self, _: &HashMap<SocketAddr, Bound>) -> std::io::Result<()> {
+        futures::future::pending().await
+    }
+}
+
+/// Sockets received from the process that spawned this
+/// one during an upgrade, or passed by systemd
+#[derive(Default)]
+struct Inherited {
+    #[cfg(unix)]
+    upgrade: Option<upgrade::Inherited>,
+    activated: Vec<std::net::TcpListener>,
+}
+
+impl Inherited {
+    #[cfg(unix)]
+    fn receive() -> Result<Self, HopperError> {
+        if let Some(upgrade) = upgrade::Inherited::receive().map_err(HopperError::Upgrade)? {
+            return Ok(Self {
+                upgrade: Some(upgrade),
+                activated: Vec::new(),
+            });
+        }
+
+        Ok(Self {
+            upgrade: None,
+            activated: systemd::listeners().map_err(HopperError::Activation)?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn receive() -> Result<Self, HopperError> {
+        Ok(Self::default())
+    }
+
+    /// takes the sockets bound to `listen`. A single socket passed by
+    /// systemd is used by a single listener regardless of its address
+    fn take(&mut self, listen: SocketAddr, single: bool) -> Vec<std::net::TcpListener> {
+        #[cfg(unix)]
+        if let Some(ref mut upgrade) = self.upgrade {
+            let sockets = upgrade.take(listen);
+            match sockets.is_empty() {
+                false => log::info!("Took over {listen} from the previous process"),
+                // the previous process keeps serving until the upgrade completes
+                true => log::warn!(
+                    "The previous process is not listening on {listen}, binding it instead"
+                ),
+            }
+
+            return sockets;
+        }
+
+        let activated = std::mem::take(&mut self.activated);
+        let (taken, rest): (Vec<_>, _) = match activated.len() {
+            1 if single => (activated, Vec::new()),
+            _ => activated
+                .into_iter()
+                .partition(|socket| socket.local_addr().ok() == Some(listen)),
+        };
+
+        if !taken.is_empty() {
+            log::info!("Using the socket passed by systemd for {listen}");
+        }
+
+        self.activated = rest;
+        taken
+    }
+
+    /// confirms the upgrade to the previous process once every listener is bound
+    fn finish(self) -> Result<(), HopperError> {
+        for socket in self.activated {
+            if let Ok(addr) = socket.local_addr() {
+                log::warn!("Closing the socket {addr} passed by systemd, no listener uses it");
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(upgrade) = self.upgrade {
+            upgrade.take_over().map_err(HopperError::Upgrade)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// whether an IPv6 listener must leave the IPv4 addresses
+/// on its port to another listener
+fn v6_only(listen: SocketAddr, addresses: &[SocketAddr]) -> bool {
+    listen.is_ipv6()
+        && addresses
+            .iter()
+            .any(|addr| addr.is_ipv4() && addr.port() == listen.port())
+}
+
+/// Settings of a listener which only apply when binding its sockets
+#[derive(PartialEq, Clone, Copy)]
+struct BindOptions {
+    reuse_port: bool,
+    backlog: i32,
+}
+
+impl From<&ListenerConfig> for BindOptions {
+    fn from(config: &ListenerConfig) -> Self {
+        Self {
+            reuse_port: config.reuse_port,
+            backlog: config.backlog,
+        }
+    }
+}
+
+/// The sockets of a listener along with the tasks accepting their connections
+struct Bound {
+    sockets: Vec<Arc<TcpListener>>,
+    listener: Arc<SwappableRouter<Listener>>,
+
+    /// own routing table, None when using the global one
+    routing: Option<Arc<RouterConfig>>,
+    options: BindOptions,
+    _accept: Vec<TaskGuard>,
+}
+
+impl Bound {
+    fn new(
+        server: &Arc<Hopper>,
+        sockets: Vec<TcpListener>,
+        options: BindOptions,
+        (listener, routing): (Listener, Option<Arc<RouterConfig>>),
+    ) -> Self {
+        let sockets: Vec<_> = sockets.into_iter().map(Arc::new).collect();
+        let listener = Arc::new(SwappableRouter::new(listener));
+
+        if let Ok(addr) = sockets[0].local_addr() {
+            match sockets.len() {
+                1 => log::info!("Listening on {addr}"),
+                n => log::info!("Listening on {addr} with {n} acceptors"),
+            }
+        }
+
+        let accept = sockets
+            .iter()
+            .map(|socket| {
+                let server = server.clone();
+                let socket = socket.clone();
+                let listener = listener.clone();
+                TaskGuard::spawn(async move { server.listen(&socket, &listener).await })
+            })
+            .collect();
+
+        Self {
+            sockets,
+            listener,
+            routing,
+            options,
+            _accept: accept,
+        }
+    }
+
+    /// routing table currently used by this listener
+    fn routing<'a>(&'a self, global: &'a Arc<RouterConfig>) -> &'a RouterConfig {
+        self.routing.as_ref().unwrap_or(global)
+    }
+}
+
+/// builds the router of a listener, which
+/// falls back to the global routing table
+fn listener(
+    config: ListenerConfig,
+    global: &Arc<RouterConfig>,
+    previous: Option<&RouterConfig>,
+) -> (Listener, Option<Arc<RouterConfig>>) {
+    let routing = config.routing.map(|mut routing| {
+        routing.start_discovery(previous);
+        Arc::new(routing)
+    });
+
+    let router: Arc<dyn Router> = match routing {
+        Some(ref routing) => routing.clone(),
+        None => global.clone(),
+    };
+
+    let listener = Listener::new(
+        router,
+        config.ip_forwarding,
+        config.proxy_protocol,
+        config.trusted_proxies,
+        config.socket,
+    );
+    (listener, routing)
+}
+
+/// binds the sockets of a listener which haven't been inherited, one for
+/// each acceptor. Only failing to bind the first one is an error
+fn bind(
+    config: &ListenerConfig,
+    v6_only: bool,
+    mut sockets: Vec<std::net::TcpListener>,
+) -> std::io::Result<Vec<TcpListener>> {
+    let listen = config.listen;
+
+    if sockets.is_empty() {
+        sockets.push(server::listener::bind(
+            listen,
+            v6_only,
+            config.reuse_port,
+            config.backlog,
+        )?);
+    }
+
+    while sockets.len() < config.acceptors() {
+        match server::listener::bind(listen, v6_only, true, config.backlog) {
+            Ok(socket) => sockets.push(socket),
+            Err(err) => {
+                log::warn!(
+                    "Cannot bind more than {} sockets on {listen}: {err}",
+                    sockets.len()
+                );
+                break;
+            }
+        }
+    }
+
+    sockets
+        .into_iter()
+        .map(|socket| {
+            socket.set_nonblocking(true)?;
+            TcpListener::from_std(socket)
+        })
+        .collect()
+}
+
+// only returns a configuration if it's valid
+async fn reload_valid(watcher: &mut ConfigWatcher) -> ServerConfig {
     loop {
-        signal.recv().await;
+        select! {
+            _ = hangup() => {},
+            _ = watcher.changed() => {},
+        }
 
         log::info!("Reloading configuration...");
         match ServerConfig::read() {
@@ -35,34 +358,200 @@ async fn reload_valid() -> ServerConfig {
     }
 }
 
-#[cfg(not(target_os = "linux"))]
-fn reload_valid() -> impl futures::Future<Output = ServerConfig> {
-    futures::future::pending()
-}
-
-async fn run() -> Result<Infallible, HopperError> {
+async fn run(mut inherited: Inherited) -> Result<(), HopperError> {
     // reads configuration from Config.toml
-    let mut config = ServerConfig::read()?;
+    let config = ServerConfig::read()?;
 
-    loop {
-        let listener = TcpListener::bind(config.listen)
-            .await
-            .map_err(HopperError::Bind)?;
+    // included files are watched and hot reloaded
+    let mut watcher = ConfigWatcher::new(&config);
 
-        let metrics = config
-            .metrics
-            .map(MetricsConfig::injector)
-            .unwrap_or_else(|| Box::new(EmptyInjector));
+    let mut metrics = config.metrics;
+    let mut shutdown = config.shutdown;
+    let injector = metrics
+        .clone()
+        .map(MetricsConfig::injector)
+        .unwrap_or_else(|| Box::new(EmptyInjector));
 
-        // builds a new hopper instance with a router
-        let server = Hopper::new(Arc::new(config.routing), metrics);
+    // builds a new hopper instance. Listeners get their router
+    // swapped on reload, so metrics and sockets survive it
+    let server = Arc::new(Hopper::new(injector));
+    server.set_max_connections(config.max_connections);
+    server.set_forwarding(config.forwarding.load()?);
+    server.set_session_server(config.authentication.session_server());
 
-        select! {
-            _ = server.listen(listener) => unreachable!(),
-            _ = tokio::signal::ctrl_c() => break Err(HopperError::Signal),
-            newconfig = reload_valid() => { config = newconfig },
+    // consul pools and discovered routes are populated in the background
+    let mut routing = config.routing;
+    routing.start_discovery(None);
+    let mut routing = Arc::new(routing);
+
+    let addresses: Vec<_> = config.listeners.iter().map(|l| l.listen).collect();
+    let single = addresses.len() == 1;
+
+    let mut sockets = Vec::new();
+
+    for config in config.listeners {
+        let listen = config.listen;
+        let inherited = inherited.take(listen, single);
+        let bound =
+            bind(&config, v6_only(listen, &addresses), inherited).map_err(HopperError::Bind)?;
+
+        sockets.push((listen, bound, config));
+    }
+
+    // connections are only accepted once the previous process
+    // has stopped accepting them, after confirming the upgrade
+    inherited.finish()?;
+
+    let mut bound = HashMap::new();
+    for (listen, sockets, config) in sockets {
+        let options = BindOptions::from(&config);
+        let listener = listener(config, &routing, None);
+        bound.insert(listen, Bound::new(&server, sockets, options, listener));
+    }
+    let mut upgrader = Upgrader::new();
+
+    // whether a new process has taken over the listeners
+    let upgraded = loop {
+        let config = select! {
+            _ = terminate() => break false,
+            result = upgrader.upgrade(&bound) => match result {
+                Ok(()) => break true,
+                Err(err) => {
+                    log::error!("Upgrade failed, continuing with the current process: {err}");
+                    continue;
+                }
+            },
+            newconfig = reload_valid(&mut watcher) => newconfig,
+        };
+
+        watcher = ConfigWatcher::new(&config);
+
+        if config.metrics != metrics {
+            let injector = config
+                .metrics
+                .clone()
+                .map(MetricsConfig::injector)
+                .unwrap_or_else(|| Box::new(EmptyInjector));
+
+            server.metrics().set_injector(injector);
+            metrics = config.metrics;
+        }
+
+        server.set_max_connections(config.max_connections);
+        match config.forwarding.load() {
+            Ok(forwarding) => server.set_forwarding(forwarding),
+            Err(err) => log::error!("Keeping the previous forwarding options: {err}"),
+        }
+        server.set_session_server(config.authentication.session_server());
+        shutdown = config.shutdown;
+
+        let mut new_routing = config.routing;
+        new_routing.start_discovery(Some(&routing));
+        let new_routing = Arc::new(new_routing);
+
+        log::info!(
+            "Configuration reloaded, routes: {}",
+            routing.diff(&new_routing)
+        );
+
+        // sockets are kept open as long as their address is still configured,
+        // the others are closed before binding the new addresses
+        let addresses: Vec<_> = config.listeners.iter().map(|l| l.listen).collect();
+        let mut previous = std::mem::take(&mut bound);
+        previous.retain(|listen, _| {
+            let retained = addresses.contains(listen);
+            if !retained {
+                log::info!("Stopped listening on {listen}");
+            }
+
+            retained
+        });
+
+        for config in config.listeners {
+            let listen = config.listen;
+            let options = BindOptions::from(&config);
+
+            if let Some(mut previous) = previous.remove(&listen) {
+                if previous.options != options {
+                    log::warn!(
+                        "Changing reuse-port or backlog of {listen} requires restarting hopper"
+                    );
+                }
+
+                let (listener, own) =
+                    listener(config, &new_routing, Some(previous.routing(&routing)));
+                if let Some(ref own) = own {
+                    let diff = previous.routing(&routing).diff(own);
+                    log::info!("Routes of {listen}: {diff}");
+                }
+
+                previous.listener.swap(listener);
+                previous.routing = own;
+                bound.insert(listen, previous);
+                continue;
+            }
+
+            match bind(&config, v6_only(listen, &addresses), Vec::new()) {
+                Ok(sockets) => {
+                    let listener = listener(config, &new_routing, None);
+                    let bound_listener = Bound::new(&server, sockets, options, listener);
+                    bound.insert(listen, bound_listener);
+                }
+                Err(err) => log::error!("Cannot listen on {listen}: {err}"),
+            }
+        }
+
+        routing = new_routing;
+    };
+
+    log::info!(
+        "Shutting down, waiting up to {}s for {} connections to close",
+        shutdown.drain_timeout,
+        server.active_connections()
+    );
+
+    // stops the accept tasks but keeps the sockets
+    let sockets: Vec<_> = bound
+        .into_values()
+        .flat_map(|bound| {
+            let listener = bound.listener.load();
+            bound
+                .sockets
+                .into_iter()
+                .map(move |socket| (socket, listener.clone()))
+        })
+        .collect();
+
+    // without a message the listeners are closed right away,
+    // after an upgrade the new process is the one accepting
+    let refuse = async {
+        match shutdown.message.filter(|_| !upgraded) {
+            Some(ref message) => {
+                let refuse = sockets
+                    .iter()
+                    .map(|(socket, listener)| server.refuse(socket, listener.clone(), message));
+
+                futures::future::join_all(refuse).await;
+            }
+            None => drop(sockets),
+        }
+
+        futures::future::pending().await
+    };
+
+    select! {
+        _ = refuse => unreachable!(),
+        remaining = server.drain(Duration::from_secs(shutdown.drain_timeout)) => match remaining {
+            0 => log::info!("All connections have been closed"),
+            remaining => log::warn!("Drain timeout elapsed, dropping {remaining} open connections"),
+        },
+        _ = terminate() => {
+            log::warn!("Dropping {} open connections", server.active_connections());
+            return Err(HopperError::Signal);
         }
     }
+
+    Ok(())
 }
 
 fn main() {
@@ -72,12 +561,19 @@ fn main() {
         .init()
         .unwrap();
 
+    // the environment is read, and cleared, before the runtime spawns any thread
+    let inherited = match Inherited::receive() {
+        Ok(inherited) => inherited,
+        Err(err) => return log::error!("{err}"),
+    };
+
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_io()
         .enable_time()
         .build()
         .unwrap();
 
-    let err = rt.block_on(run()).unwrap_err();
-    log::error!("{}", err)
+    if let Err(err) = rt.block_on(run(inherited)) {
+        log::error!("{}", err)
+    }
 }
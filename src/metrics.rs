@@ -1,6 +1,17 @@
 use self::injector::{MetricsError, MetricsInjector};
-use crate::{protocol::packet_impls::State, server::client::Hostname};
-use std::{collections::HashMap, time::Duration};
+use crate::{
+    protocol::packet_impls::State,
+    server::{accept::AcceptError, client::Hostname},
+};
+use arc_swap::ArcSwap;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
     select,
     sync::mpsc::{self, Receiver},
@@ -52,6 +63,8 @@ impl MetricsGuard<'_> {
 pub struct HostnameCounter {
     total_pings: u64,
     total_game: u64,
+    /// logins of players transferred by another server
+    total_transfers: u64,
 
     open_connections: u64,
 
@@ -66,6 +79,7 @@ impl HostnameCounter {
                 match event.information.state {
                     State::Login => self.total_game = self.total_game.wrapping_add(1),
                     State::Status => self.total_pings = self.total_pings.wrapping_add(1),
+                    State::Transfer => self.total_transfers = self.total_transfers.wrapping_add(1),
                 }
 
                 self.open_connections = self
@@ -93,9 +107,54 @@ impl HostnameCounter {
 #[allow(clippy::mutable_key_type)] // allowed for bytes::Bytes
 pub type Counters = HashMap<Hostname, HostnameCounter>;
 
+/// Errors and rejections of the accept loops, shared by every listener
+#[derive(Default, Debug)]
+pub struct AcceptCounters {
+    errors: AtomicU64,
+    exhausted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl AcceptCounters {
+    pub fn record(&self, error: AcceptError) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+
+        if error == AcceptError::Exhausted {
+            self.exhausted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// a connection was closed right away because of the connection cap
+    pub fn reject(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> AcceptCounter {
+        AcceptCounter {
+            errors: self.errors.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct AcceptCounter {
+    /// every failed accept, including the exhausted ones
+    pub errors: u64,
+    /// accepts failed because of file descriptor or memory exhaustion
+    pub exhausted: u64,
+    /// connections closed because of the connection cap
+    pub rejected: u64,
+}
+
+type SharedInjector = Arc<ArcSwap<Box<dyn MetricsInjector>>>;
+
 pub struct Metrics {
     sender: mpsc::Sender<Event>,
     handler: JoinHandle<()>,
+    injector: SharedInjector,
+    accept: Arc<AcceptCounters>,
 }
 
 impl Drop for Metrics {
@@ -108,19 +167,44 @@ impl Metrics {
     pub fn init(injector: Box<dyn MetricsInjector>) -> Self {
         let (sender, receiver) = mpsc::channel::<Event>(8096);
 
-        let handler = tokio::spawn(Metrics::metrics_handler(receiver, injector));
+        let injector: SharedInjector = Arc::new(ArcSwap::from_pointee(injector));
+        let accept: Arc<AcceptCounters> = Default::default();
+        let handler = tokio::spawn(Metrics::metrics_handler(
+            receiver,
+            injector.clone(),
+            accept.clone(),
+        ));
+
+        Self {
+            sender,
+            handler,
+            injector,
+            accept,
+        }
+    }
+
+    /// replaces the injector counters are logged with,
+    /// without resetting them
+    pub fn set_injector(&self, injector: Box<dyn MetricsInjector>) {
+        self.injector.store(Arc::new(injector));
+    }
 
-        Self { sender, handler }
+    pub fn accept(&self) -> &AcceptCounters {
+        &self.accept
     }
 
-    pub fn guard(&self, hostname: Hostname, state: State) -> MetricsGuard {
+    pub fn guard(&self, hostname: Hostname, state: State) -> MetricsGuard<'_> {
         MetricsGuard {
             sender: &self.sender,
             information: GuardInformation { hostname, state },
         }
     }
 
-    async fn metrics_handler(mut receiver: Receiver<Event>, injector: Box<dyn MetricsInjector>) {
+    async fn metrics_handler(
+        mut receiver: Receiver<Event>,
+        injector: SharedInjector,
+        accept: Arc<AcceptCounters>,
+    ) {
         let mut counters: Counters = Default::default();
 
         let mut register_interval = time::interval(Duration::from_secs(5));
@@ -130,7 +214,8 @@ impl Metrics {
                 biased;
                 Some(event) = receiver.recv() => event,
                 _ = register_interval.tick() => {
-                    if let Err(err) = injector.log(&counters).await { log::error!("InfluxDB reported an error: {err}") };
+                    let accept = accept.snapshot();
+                    if let Err(err) = injector.load().log(&counters, &accept).await { log::error!("InfluxDB reported an error: {err}") };
                     continue
                 },
             };
@@ -1,6 +1,6 @@
 use std::ops::Deref;
 
-use super::{Counters, HostnameCounter, MetricsError, MetricsInjector};
+use super::{AcceptCounter, Counters, HostnameCounter, MetricsError, MetricsInjector};
 use async_trait::async_trait;
 use futures::stream;
 use influxdb2::models::DataPoint;
@@ -13,8 +13,8 @@ pub struct InfluxInjector {
 
 #[async_trait]
 impl MetricsInjector for InfluxInjector {
-    async fn log(&self, counters: &Counters) -> Result<(), MetricsError> {
-        let writes: Vec<_> = counters
+    async fn log(&self, counters: &Counters, accept: &AcceptCounter) -> Result<(), MetricsError> {
+        let mut writes: Vec<_> = counters
             .iter()
             .map(|(connecting_host, metrics)| {
                 // destructuring ensures that no field will
@@ -22,6 +22,7 @@ impl MetricsInjector for InfluxInjector {
                 let HostnameCounter {
                     total_pings,
                     total_game,
+                    total_transfers,
                     open_connections,
                     serverbound_traffic,
                     clientbound_traffic,
@@ -32,6 +33,7 @@ impl MetricsInjector for InfluxInjector {
                     .tag("destination_hostname", connecting_host.deref())
                     .field("total_pings", i64::try_from(total_pings).unwrap())
                     .field("total_game", i64::try_from(total_game).unwrap())
+                    .field("total_transfers", i64::try_from(total_transfers).unwrap())
                     .field("open_connections", i64::try_from(open_connections).unwrap())
                     .field(
                         "serverbound_traffic",
@@ -46,6 +48,22 @@ impl MetricsInjector for InfluxInjector {
             })
             .collect();
 
+        let AcceptCounter {
+            errors,
+            exhausted,
+            rejected,
+        } = *accept;
+
+        writes.push(
+            DataPoint::builder("accept")
+                .tag("host", &self.host)
+                .field("errors", i64::try_from(errors).unwrap())
+                .field("exhausted", i64::try_from(exhausted).unwrap())
+                .field("rejected", i64::try_from(rejected).unwrap())
+                .build()
+                .unwrap(),
+        );
+
         self.client
             .write(&self.bucket, stream::iter(writes))
             .await
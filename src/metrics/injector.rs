@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use thiserror::Error;
 
-use super::Counters;
+use super::{AcceptCounter, Counters};
 
 #[derive(Debug, Error)]
 #[error(transparent)]
@@ -9,14 +9,14 @@ pub struct MetricsError(#[from] pub Box<dyn std::error::Error + Send + Sync + 's
 
 #[async_trait]
 pub trait MetricsInjector: Send + Sync {
-    async fn log(&self, counters: &Counters) -> Result<(), MetricsError>;
+    async fn log(&self, counters: &Counters, accept: &AcceptCounter) -> Result<(), MetricsError>;
 }
 
 pub struct EmptyInjector;
 
 #[async_trait]
 impl MetricsInjector for EmptyInjector {
-    async fn log(&self, _: &Counters) -> Result<(), MetricsError> {
+    async fn log(&self, _: &Counters, _: &AcceptCounter) -> Result<(), MetricsError> {
         Ok(())
     }
 }
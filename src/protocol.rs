@@ -2,3 +2,5 @@ pub mod connection;
 pub mod packet_impls;
 pub mod types;
 pub mod packet;
+pub mod crypto;
+pub mod login;
@@ -8,10 +8,13 @@ use netherite::{
 };
 use thiserror::Error;
 
-use tokio::net::TcpStream;
-use tokio_util::codec::Framed;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_util::codec::{Framed, FramedParts};
 
-pub type Codec = Framed<TcpStream, MinecraftCodec>;
+pub type Codec<S = TcpStream> = Framed<S, MinecraftCodec>;
 
 #[derive(Debug, Error)]
 pub enum ConnectionError {
@@ -21,17 +24,33 @@ pub enum ConnectionError {
     Codec(#[from] CodecError),
 }
 
-pub struct Connection {
-    inner: Codec,
+/// Minecraft connection over a stream, TCP unless specified
+pub struct Connection<S = TcpStream> {
+    inner: Codec<S>,
 }
 
-impl Connection {
-    pub fn new(inner: TcpStream) -> Self {
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(inner: S) -> Self {
         Self {
             inner: Codec::new(inner, MinecraftCodec::default()),
         }
     }
 
+    /// Wraps a socket from which `read_buf` has already
+    /// been read, decoding it before any further read
+    pub fn with_buffer(inner: S, read_buf: BytesMut) -> Self {
+        let mut parts = FramedParts::new::<&RawPacket>(inner, MinecraftCodec::default());
+        parts.read_buf = read_buf;
+
+        Self {
+            inner: Codec::from_parts(parts),
+        }
+    }
+
+    pub fn socket(&self) -> &S {
+        self.inner.get_ref()
+    }
+
     pub fn write_buffer(&mut self) -> &mut BytesMut {
         self.inner.write_buffer_mut()
     }
@@ -44,14 +63,22 @@ impl Connection {
         self.inner.read_buffer().is_empty() && self.inner.write_buffer().is_empty()
     }
 
-    /// Downgrades this socket into a raw `TcpStream`.
+    /// Downgrades this connection into the raw stream.
     /// Note: all internal buffers must be empty to
     /// avoid any data losses
-    pub fn into_socket(self) -> TcpStream {
+    pub fn into_socket(self) -> S {
         debug_assert!(self.is_detachable());
         self.inner.into_inner()
     }
 
+    /// Downgrades this connection into the raw stream along with the
+    /// data read but not decoded yet. The write buffer must be empty
+    pub fn into_parts(self) -> (S, BytesMut) {
+        debug_assert!(self.inner.write_buffer().is_empty());
+        let parts = self.inner.into_parts();
+        (parts.io, parts.read_buf)
+    }
+
     pub async fn read_packet(&mut self) -> Result<RawPacket, ConnectionError> {
         self.inner.try_next().await?.ok_or(ConnectionError::Eof)
     }
@@ -60,7 +87,11 @@ impl Connection {
         &mut self,
         packet: impl AsRef<RawPacket>,
     ) -> Result<(), ConnectionError> {
-        let packet = packet.as_ref();
+        self.feed_raw(packet.as_ref()).await
+    }
+
+    /// feeds a packet which has been read but not decoded
+    pub async fn feed_raw(&mut self, packet: &RawPacket) -> Result<(), ConnectionError> {
         self.inner.feed(packet).await.map_err(Into::into)
     }
 
@@ -72,7 +103,7 @@ impl Connection {
     }
 
     pub async fn flush(&mut self) -> Result<(), ConnectionError> {
-        <Codec as SinkExt<&RawPacket>>::flush(&mut self.inner)
+        <Codec<S> as SinkExt<&RawPacket>>::flush(&mut self.inner)
             .await
             .map_err(Into::into)
     }
@@ -0,0 +1,62 @@
+//! Encryption of online-mode logins
+
+use std::fmt::Write;
+
+use ring::digest::{Context, SHA1_FOR_LEGACY_USE_ONLY};
+
+pub mod cfb8;
+
+/// SHA-1 digest formatted as a signed two's complement number,
+/// as expected by the session servers
+fn hex_digest(digest: &[u8]) -> String {
+    let mut digest = digest.to_vec();
+    let negative = digest[0] & 0x80 != 0;
+
+    if negative {
+        // two's complement negation, invert and add one
+        let mut carry = true;
+        for byte in digest.iter_mut().rev() {
+            let (sum, overflow) = (!*byte).overflowing_add(carry as u8);
+            *byte = sum;
+            carry = overflow;
+        }
+    }
+
+    let mut hex = String::new();
+    for byte in digest {
+        write!(hex, "{byte:02x}").ok();
+    }
+
+    let hex = hex.trim_start_matches('0');
+    match negative {
+        true => format!("-{hex}"),
+        false => hex.into(),
+    }
+}
+
+/// server id the client sends to the session server when joining
+pub fn server_hash(server_id: &str, secret: &[u8], public_key: &[u8]) -> String {
+    let mut context = Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+    context.update(server_id.as_bytes());
+    context.update(secret);
+    context.update(public_key);
+
+    hex_digest(context.finish().as_ref())
+}
+
+#[cfg(test)]
+mod test {
+    use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+
+    use super::hex_digest;
+
+    #[test]
+    fn server_hash() {
+        let hash =
+            |name: &str| hex_digest(digest(&SHA1_FOR_LEGACY_USE_ONLY, name.as_bytes()).as_ref());
+
+        assert_eq!(hash("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(hash("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(hash("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+}
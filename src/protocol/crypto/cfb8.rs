@@ -0,0 +1,162 @@
+//! AES/CFB8 stream cipher of encrypted connections,
+//! keyed and initialized with the shared secret
+
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use aes::Aes128;
+use bytes::{Buf, BytesMut};
+use cfb8::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+type Encryptor = cfb8::Encryptor<Aes128>;
+type Decryptor = cfb8::Decryptor<Aes128>;
+
+/// Reader decrypting everything read from `inner`
+pub struct Decrypting<R> {
+    inner: R,
+    cipher: Decryptor,
+}
+
+impl<R> Decrypting<R> {
+    pub fn new(inner: R, secret: &[u8; 16]) -> Self {
+        Self {
+            inner,
+            cipher: Decryptor::new(secret.into(), secret.into()),
+        }
+    }
+
+    /// decrypts `data` read from `inner` by other means,
+    /// blocks of the cipher being single bytes
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data.chunks_mut(1) {
+            self.cipher.decrypt_block_mut(byte.into());
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Decrypting<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.decrypt(&mut buf.filled_mut()[filled..]);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writer encrypting everything written to `inner`. Data is only
+/// accepted once the previous write has been entirely sent, as
+/// the cipher can't go back on bytes it has already encrypted
+pub struct Encrypting<W> {
+    inner: W,
+    cipher: Encryptor,
+    /// encrypted data not written yet
+    pending: BytesMut,
+}
+
+impl<W> Encrypting<W> {
+    pub fn new(inner: W, secret: &[u8; 16]) -> Self {
+        Self {
+            inner,
+            cipher: Encryptor::new(secret.into(), secret.into()),
+            pending: BytesMut::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Encrypting<W> {
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            self.pending.advance(written);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Encrypting<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+
+        this.pending.extend_from_slice(buf);
+        for byte in this.pending.chunks_mut(1) {
+            this.cipher.encrypt_block_mut(byte.into());
+        }
+
+        // whatever can't be written now is sent by the next write or flush
+        if let Poll::Ready(Err(err)) = this.poll_pending(cx) {
+            return Poll::Ready(Err(err));
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{Decrypting, Encrypting};
+
+    #[tokio::test]
+    async fn stream() {
+        let secret = [42; 16];
+        let (writer, reader) = tokio::io::duplex(8);
+
+        let mut writer = Encrypting::new(writer, &secret);
+        let mut reader = Decrypting::new(reader, &secret);
+
+        let message: Vec<u8> = (0..=255).collect();
+        let written = message.clone();
+        let write = tokio::spawn(async move {
+            writer.write_all(&written).await.unwrap();
+            writer.flush().await.unwrap();
+        });
+
+        let mut read = vec![0; message.len()];
+        reader.read_exact(&mut read).await.unwrap();
+        write.await.unwrap();
+
+        assert_eq!(read, message);
+    }
+}
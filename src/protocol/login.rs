@@ -0,0 +1,242 @@
+//! Online-mode login, run by hopper on behalf of offline-mode backends
+
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    pkcs8::{self, DecodePublicKey, EncodePublicKey},
+    rand_core::OsRng,
+    sha2::Sha256,
+    signature::Verifier,
+    Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::{
+    connection::{Connection, ConnectionError},
+    crypto,
+    packet::{DecodedPacket, ProtoError},
+    packet_impls::{EncryptionRequest, EncryptionResponse, VerifyToken},
+    types::{PlayerUuid, Property},
+};
+
+pub const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+
+/// size of the modulus, the one used by vanilla servers
+const KEY_BITS: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum LoginError {
+    #[error("{0}")]
+    Connection(#[from] ConnectionError),
+
+    #[error("{0}")]
+    Encoding(#[from] ProtoError),
+
+    #[error("the client sent an invalid shared secret or verify token")]
+    Secret,
+
+    #[error("the client sent an invalid verify token signature")]
+    Signature,
+
+    #[error("session server error: {0}")]
+    Session(#[from] reqwest::Error),
+
+    #[error("Failed to verify username!")]
+    Unverified,
+
+    #[error("cannot generate the key pair: {0}")]
+    KeyPair(#[from] rsa::Error),
+
+    #[error("cannot generate the key pair: {0}")]
+    KeyPairTask(#[from] tokio::task::JoinError),
+
+    #[error("only TCP connections can be encrypted")]
+    Unencryptable,
+}
+
+/// RSA key pair the shared secret of encrypted logins is sent with
+pub struct KeyPair {
+    private: RsaPrivateKey,
+    /// DER encoded SubjectPublicKeyInfo sent to clients
+    public_key: Vec<u8>,
+}
+
+impl KeyPair {
+    /// generates a new key pair, which takes a while
+    pub fn generate() -> Result<Self, LoginError> {
+        let private = RsaPrivateKey::new(&mut OsRng, KEY_BITS)?;
+        let public_key = private
+            .to_public_key()
+            .to_public_key_der()
+            .map_err(|err| rsa::Error::from(pkcs8::Error::from(err)))?;
+
+        Ok(Self {
+            private,
+            public_key: public_key.into_vec(),
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// decrypts and unpads `data`, None if it is invalid. Blinding
+    /// keeps the time it takes independent of the private key
+    pub fn decrypt(&self, data: &[u8]) -> Option<Vec<u8>> {
+        self.private
+            .decrypt_blinded(&mut OsRng, Pkcs1v15Encrypt, data)
+            .ok()
+    }
+
+    /// pads and encrypts `data` with the public key, as clients do
+    #[cfg(test)]
+    pub fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        self.private
+            .to_public_key()
+            .encrypt(&mut OsRng, Pkcs1v15Encrypt, data)
+            .unwrap()
+    }
+}
+
+/// Profile of a player authenticated by the session server
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    pub id: PlayerUuid,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<Property>,
+}
+
+/// Session server, with the `hasJoined` endpoint
+/// of `https://sessionserver.mojang.com`
+pub struct SessionServer {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl Default for SessionServer {
+    fn default() -> Self {
+        Self::new(MOJANG_SESSION_SERVER.into())
+    }
+}
+
+impl SessionServer {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// profile of `username` if it joined the server identified by `server_hash`
+    pub async fn has_joined(
+        &self,
+        username: &str,
+        server_hash: &str,
+    ) -> Result<Profile, LoginError> {
+        let response = self
+            .http
+            .get(format!(
+                "{}/session/minecraft/hasJoined",
+                self.url.trim_end_matches('/')
+            ))
+            .query(&[("username", username), ("serverId", server_hash)])
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // players who did not join get an empty response
+        match response.status() {
+            reqwest::StatusCode::OK => Ok(response.json().await?),
+            _ => Err(LoginError::Unverified),
+        }
+    }
+}
+
+/// checks the verify token signed by a 1.19 client with `chat_key`,
+/// the DER encoded key it sent along with its login start
+fn verify_signature(
+    chat_key: &[u8],
+    verify_token: &[u8],
+    salt: i64,
+    signature: &[u8],
+) -> Result<(), LoginError> {
+    let chat_key =
+        RsaPublicKey::from_public_key_der(chat_key).map_err(|_| LoginError::Signature)?;
+    let signature = Signature::try_from(signature).map_err(|_| LoginError::Signature)?;
+
+    let mut signed = verify_token.to_vec();
+    signed.extend_from_slice(&salt.to_be_bytes());
+
+    VerifyingKey::<Sha256>::new(chat_key)
+        .verify(&signed, &signature)
+        .map_err(|_| LoginError::Signature)
+}
+
+/// sends an encryption request and reads the response, returning the
+/// shared secret the connection must be encrypted with from then on.
+/// 1.19 to 1.19.2 clients may sign the verify token with `chat_key`
+pub async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    keys: &KeyPair,
+    protocol_version: i32,
+    chat_key: Option<&[u8]>,
+) -> Result<[u8; 16], LoginError> {
+    let mut verify_token = [0; 4];
+    SystemRandom::new()
+        .fill(&mut verify_token)
+        .map_err(|_| LoginError::Secret)?;
+
+    let request = EncryptionRequest::new(keys.public_key(), &verify_token, protocol_version);
+    connection.feed_packet(request).await?;
+    connection.flush().await?;
+
+    let response: DecodedPacket<EncryptionResponse> = connection.read_packet().await?.try_into()?;
+    match response
+        .verify_token(protocol_version)
+        .map_err(ProtoError::from)?
+    {
+        VerifyToken::Encrypted(token) => {
+            if keys.decrypt(&token).as_deref() != Some(&verify_token[..]) {
+                return Err(LoginError::Secret);
+            }
+        }
+        VerifyToken::Signed { salt, signature } => {
+            let chat_key = chat_key.ok_or(LoginError::Signature)?;
+            verify_signature(chat_key, &verify_token, salt, &signature)?;
+        }
+    }
+
+    keys.decrypt(&response.shared_secret)
+        .and_then(|secret| secret.try_into().ok())
+        .ok_or(LoginError::Secret)
+}
+
+/// server id of the encryption request sent by [`exchange`]
+pub fn server_hash(secret: &[u8], keys: &KeyPair) -> String {
+    crypto::server_hash("", secret, keys.public_key())
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyPair;
+
+    #[test]
+    fn key_pair() {
+        let keys = KeyPair::generate().unwrap();
+        assert_eq!(keys.public_key().len(), 162);
+
+        let secret = [7; 16];
+        let encrypted = keys.encrypt(&secret);
+        assert_eq!(encrypted.len(), 128);
+        assert_eq!(keys.decrypt(&encrypted).unwrap(), secret);
+
+        assert_eq!(keys.decrypt(&[0; 128]), None);
+        assert_eq!(keys.decrypt(&secret), None);
+    }
+}
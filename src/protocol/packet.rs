@@ -29,6 +29,21 @@ impl<T> From<DecodedPacket<T>> for RawPacket {
     }
 }
 
+/// copies share the buffer of the original packet
+impl<T: Clone> Clone for DecodedPacket<T> {
+    fn clone(&self) -> Self {
+        let packet = RawPacket {
+            packet_id: self.packet.packet_id,
+            data: self.packet.data.clone(),
+        };
+
+        Self {
+            packet,
+            data: self.data.clone(),
+        }
+    }
+}
+
 impl<T: PacketId> DecodedPacket<T> {
     /// gets owned data and drops original packet
     pub fn into_data(self) -> T {
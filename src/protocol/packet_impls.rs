@@ -1,10 +1,12 @@
-use bytes::Buf;
+use bytes::{Buf, BufMut, Bytes};
 use netherite::{
     encoding::{packetid::PacketId, str::Str, varint::VarInt},
     DeError, Deserialize, Serialize,
 };
 use serde_json::json;
 
+use super::types::PlayerUuid;
+
 pub struct JsonChat(String);
 
 impl JsonChat {
@@ -34,6 +36,8 @@ impl PacketId for Disconnect<'_> {
 pub enum State {
     Status = 1,
     Login = 2,
+    /// login of a client transferred by another server, since 1.20.5
+    Transfer = 3,
 }
 
 impl Serialize for State {
@@ -53,6 +57,7 @@ impl Deserialize for State {
         match state {
             1 => Ok(State::Status),
             2 => Ok(State::Login),
+            3 => Ok(State::Transfer),
             _ => Err(DeError::InvalidData),
         }
     }
@@ -72,6 +77,17 @@ impl PacketId for Handshake {
     const ID: i32 = 0x00;
 }
 
+impl Clone for Handshake {
+    fn clone(&self) -> Self {
+        Self {
+            protocol_version: VarInt(self.protocol_version.0),
+            server_address: self.server_address.clone(),
+            server_port: self.server_port,
+            next_state: self.next_state,
+        }
+    }
+}
+
 /// All-owned for creation
 /// of a new handshake
 #[derive(Serialize, Debug)]
@@ -99,11 +115,452 @@ impl PacketId for NewHandshake {
     const ID: i32 = 0x00;
 }
 
-#[derive(Debug, Deserialize)]
+/// 1.19, signature data may follow the username
+const PROTOCOL_1_19: i32 = 759;
+/// 1.19.1, the client may send its UUID
+const PROTOCOL_1_19_1: i32 = 760;
+/// 1.19.3, signature data has been removed
+const PROTOCOL_1_19_3: i32 = 761;
+/// 1.20.2, the UUID is always sent
+const PROTOCOL_1_20_2: i32 = 764;
+/// 1.20.5, players can be transferred to another server
+pub const PROTOCOL_1_20_5: i32 = 766;
+/// 1.21.2, login success lost the strict error handling field
+const PROTOCOL_1_21_2: i32 = 768;
+
+#[derive(Debug)]
+/// Login start, whose layout after the username
+/// depends on the protocol version
 pub struct LoginStart {
     pub username: Str,
+    rest: Bytes,
+}
+
+impl Deserialize for LoginStart {
+    fn deserialize(mut buffer: impl Buf) -> Result<Self, DeError> {
+        let username = Str::deserialize(&mut buffer)?;
+        let rest = buffer.copy_to_bytes(buffer.remaining());
+
+        Ok(Self { username, rest })
+    }
+}
+
+impl LoginStart {
+    /// UUID sent by the client, if `protocol_version` has one
+    pub fn uuid(&self, protocol_version: i32) -> Result<Option<PlayerUuid>, DeError> {
+        let mut rest = self.rest.clone();
+
+        match protocol_version {
+            ..=758 => Ok(None),
+            PROTOCOL_1_19..=PROTOCOL_1_19_1 => {
+                // timestamp, public key and signature
+                if bool::deserialize(&mut rest)? {
+                    i64::deserialize(&mut rest)?;
+                    Bytes::deserialize(&mut rest)?;
+                    Bytes::deserialize(&mut rest)?;
+                }
+
+                match protocol_version {
+                    PROTOCOL_1_19 => Ok(None),
+                    _ => Option::<PlayerUuid>::deserialize(rest),
+                }
+            }
+            PROTOCOL_1_19_3..PROTOCOL_1_20_2 => Option::<PlayerUuid>::deserialize(rest),
+            _ => PlayerUuid::deserialize(rest).map(Some),
+        }
+    }
+
+    /// DER encoded public chat key of 1.19 to 1.19.2 clients, which
+    /// may sign the verify token with it instead of encrypting it
+    pub fn chat_key(&self, protocol_version: i32) -> Result<Option<Bytes>, DeError> {
+        let mut rest = self.rest.clone();
+
+        match protocol_version {
+            PROTOCOL_1_19..=PROTOCOL_1_19_1 if bool::deserialize(&mut rest)? => {
+                i64::deserialize(&mut rest)?;
+                Bytes::deserialize(rest).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 impl PacketId for LoginStart {
     const ID: i32 = 0x00;
 }
+
+/// Status Response JSON payload
+#[derive(Debug)]
+pub struct StatusJson(String);
+
+impl StatusJson {
+    pub fn new(version: &str, protocol: i32, description: &str) -> Self {
+        Self::detailed(version, protocol, description, (0, 0), None)
+    }
+
+    /// status listing `(online, max)` players, and a
+    /// favicon as a `data:image/png;base64,` URI
+    pub fn detailed(
+        version: &str,
+        protocol: i32,
+        description: &str,
+        (online, max): (u32, u32),
+        favicon: Option<&str>,
+    ) -> Self {
+        let mut json = json!({
+            "version": { "name": version, "protocol": protocol },
+            "players": { "max": max, "online": online },
+            "description": { "text": description },
+        });
+
+        if let Some(favicon) = favicon {
+            json["favicon"] = favicon.into();
+        }
+
+        Self(serde_json::to_string(&json).unwrap())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<serde_json::Value> for StatusJson {
+    fn from(json: serde_json::Value) -> Self {
+        Self(json.to_string())
+    }
+}
+
+/// Status Response of a backend, decoded as its JSON payload
+impl Deserialize for StatusJson {
+    fn deserialize(buffer: impl Buf) -> Result<Self, DeError> {
+        Ok(Self(Str::deserialize(buffer)?.to_string()))
+    }
+}
+
+impl PacketId for StatusJson {
+    const ID: i32 = 0x00;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusRequest {}
+
+impl PacketId for StatusRequest {
+    const ID: i32 = 0x00;
+}
+
+#[derive(Serialize)]
+pub struct StatusResponse<'a> {
+    json: &'a str,
+}
+
+impl<'a> StatusResponse<'a> {
+    pub fn from_json(status: &'a StatusJson) -> Self {
+        Self { json: &status.0 }
+    }
+}
+
+impl PacketId for StatusResponse<'_> {
+    const ID: i32 = 0x00;
+}
+
+/// Ping request, which the Pong response
+/// mirrors in both packet id and payload
+#[derive(Serialize, Deserialize)]
+pub struct Ping {
+    pub payload: i64,
+}
+
+impl PacketId for Ping {
+    const ID: i32 = 0x01;
+}
+
+/// Custom query sent by the server during login, the
+/// rest of the packet is the channel specific payload
+#[derive(Debug)]
+pub struct LoginPluginRequest {
+    pub message_id: VarInt,
+    pub channel: Str,
+    pub data: Bytes,
+}
+
+impl Deserialize for LoginPluginRequest {
+    fn deserialize(mut buffer: impl Buf) -> Result<Self, DeError> {
+        let message_id = VarInt::deserialize(&mut buffer)?;
+        let channel = Str::deserialize(&mut buffer)?;
+        let data = buffer.copy_to_bytes(buffer.remaining());
+
+        Ok(Self {
+            message_id,
+            channel,
+            data,
+        })
+    }
+}
+
+impl PacketId for LoginPluginRequest {
+    const ID: i32 = 0x04;
+}
+
+/// Answer to a [`LoginPluginRequest`], data
+/// is None when the channel is not understood
+pub struct LoginPluginResponse<'a> {
+    pub message_id: VarInt,
+    pub data: Option<&'a [u8]>,
+}
+
+impl Serialize for LoginPluginResponse<'_> {
+    fn serialize(&self, mut buf: impl BufMut) {
+        self.message_id.serialize(&mut buf);
+        self.data.is_some().serialize(&mut buf);
+
+        // not length prefixed, it takes the rest of the packet
+        if let Some(data) = self.data {
+            buf.put_slice(data);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.message_id.size() + 1 + self.data.map_or(0, <[u8]>::len)
+    }
+}
+
+impl PacketId for LoginPluginResponse<'_> {
+    const ID: i32 = 0x02;
+}
+
+/// Asks the client to enable encryption and, as it is
+/// sent with an empty server id, to join through the session server
+pub struct EncryptionRequest<'a> {
+    public_key: &'a [u8],
+    verify_token: &'a [u8],
+    protocol_version: i32,
+}
+
+impl<'a> EncryptionRequest<'a> {
+    pub fn new(public_key: &'a [u8], verify_token: &'a [u8], protocol_version: i32) -> Self {
+        Self {
+            public_key,
+            verify_token,
+            protocol_version,
+        }
+    }
+
+    /// the should authenticate field, sent since 1.20.5
+    fn authenticate(&self) -> bool {
+        self.protocol_version >= PROTOCOL_1_20_5
+    }
+}
+
+impl Serialize for EncryptionRequest<'_> {
+    fn serialize(&self, mut buf: impl BufMut) {
+        "".serialize(&mut buf);
+        self.public_key.serialize(&mut buf);
+        self.verify_token.serialize(&mut buf);
+
+        if self.authenticate() {
+            true.serialize(&mut buf);
+        }
+    }
+
+    fn size(&self) -> usize {
+        "".size()
+            + self.public_key.size()
+            + self.verify_token.size()
+            + usize::from(self.authenticate())
+    }
+}
+
+impl PacketId for EncryptionRequest<'_> {
+    const ID: i32 = 0x01;
+}
+
+#[derive(Debug)]
+/// Encryption response, both fields encrypted with the public key
+/// of the server. Its layout depends on the protocol version
+pub struct EncryptionResponse {
+    pub shared_secret: Bytes,
+    rest: Bytes,
+}
+
+impl Deserialize for EncryptionResponse {
+    fn deserialize(mut buffer: impl Buf) -> Result<Self, DeError> {
+        let shared_secret = Bytes::deserialize(&mut buffer)?;
+        let rest = buffer.copy_to_bytes(buffer.remaining());
+
+        Ok(Self {
+            shared_secret,
+            rest,
+        })
+    }
+}
+
+/// Verify token sent back by the client
+pub enum VerifyToken {
+    /// encrypted with the public key of the server
+    Encrypted(Bytes),
+    /// signed by 1.19 to 1.19.2 clients with their chat key,
+    /// the token being followed by `salt` in the signed data
+    Signed { salt: i64, signature: Bytes },
+}
+
+impl EncryptionResponse {
+    pub fn verify_token(&self, protocol_version: i32) -> Result<VerifyToken, DeError> {
+        let mut rest = self.rest.clone();
+
+        match protocol_version {
+            PROTOCOL_1_19..=PROTOCOL_1_19_1 if !bool::deserialize(&mut rest)? => {
+                let salt = i64::deserialize(&mut rest)?;
+                let signature = Bytes::deserialize(rest)?;
+                Ok(VerifyToken::Signed { salt, signature })
+            }
+            _ => Bytes::deserialize(rest).map(VerifyToken::Encrypted),
+        }
+    }
+}
+
+impl PacketId for EncryptionResponse {
+    const ID: i32 = 0x01;
+}
+
+/// Login success of an offline-mode player, with no properties
+pub struct LoginSuccess<'a> {
+    uuid: PlayerUuid,
+    username: &'a str,
+    protocol_version: i32,
+}
+
+impl<'a> LoginSuccess<'a> {
+    pub fn new(uuid: PlayerUuid, username: &'a str, protocol_version: i32) -> Self {
+        Self {
+            uuid,
+            username,
+            protocol_version,
+        }
+    }
+
+    /// strict error handling, only sent from 1.20.5 to 1.21.1
+    fn strict(&self) -> bool {
+        (PROTOCOL_1_20_5..PROTOCOL_1_21_2).contains(&self.protocol_version)
+    }
+}
+
+impl Serialize for LoginSuccess<'_> {
+    fn serialize(&self, mut buf: impl BufMut) {
+        buf.put_slice(self.uuid.as_bytes());
+        self.username.serialize(&mut buf);
+        // no properties
+        VarInt(0).serialize(&mut buf);
+
+        if self.strict() {
+            false.serialize(&mut buf);
+        }
+    }
+
+    fn size(&self) -> usize {
+        16 + self.username.size() + VarInt(0).size() + usize::from(self.strict())
+    }
+}
+
+impl PacketId for LoginSuccess<'_> {
+    const ID: i32 = 0x02;
+}
+
+/// Sent by the client when entering the configuration phase
+#[derive(Deserialize)]
+pub struct LoginAcknowledged {}
+
+impl PacketId for LoginAcknowledged {
+    const ID: i32 = 0x03;
+}
+
+/// Tells the client, in the configuration phase, to connect to another server
+#[derive(Serialize)]
+pub struct Transfer<'a> {
+    host: &'a str,
+    port: VarInt,
+}
+
+impl<'a> Transfer<'a> {
+    pub fn new(host: &'a str, port: u16) -> Self {
+        Self {
+            host,
+            port: VarInt(port.into()),
+        }
+    }
+}
+
+impl PacketId for Transfer<'_> {
+    const ID: i32 = 0x0B;
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::{BufMut, BytesMut};
+    use netherite::{encoding::varint::VarInt, Deserialize, Serialize};
+
+    use super::{Handshake, LoginStart, NewHandshake, State};
+
+    const UUID: u128 = 0x069a79f444e94726a5befca90e38aaf5;
+
+    fn login_start(rest: &[u8]) -> LoginStart {
+        let mut packet = BytesMut::new();
+        "Notch".serialize(&mut packet);
+        packet.put_slice(rest);
+
+        LoginStart::deserialize(packet.freeze()).unwrap()
+    }
+
+    fn uuid(login_start: &LoginStart, protocol_version: i32) -> Option<String> {
+        let uuid = login_start.uuid(protocol_version).unwrap();
+        uuid.map(|uuid| uuid.to_string())
+    }
+
+    #[test]
+    fn login_start_uuid() {
+        let expected = Some("069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string());
+
+        // 1.18.2
+        assert_eq!(uuid(&login_start(&[]), 758), None);
+
+        // 1.19.2, without signature data
+        let mut rest = vec![0, 1];
+        rest.extend_from_slice(&UUID.to_be_bytes());
+        assert_eq!(uuid(&login_start(&rest), 760), expected);
+
+        // 1.19.2, with signature data
+        let mut rest = vec![1];
+        rest.extend_from_slice(&0i64.to_be_bytes());
+        rest.extend_from_slice(&[2, 0xAA, 0xBB, 1, 0xCC, 1]);
+        rest.extend_from_slice(&UUID.to_be_bytes());
+        assert_eq!(uuid(&login_start(&rest), 760), expected);
+        assert_eq!(uuid(&login_start(&rest[..rest.len() - 17]), 759), None);
+
+        // 1.20.1, optional UUID
+        assert_eq!(uuid(&login_start(&[0]), 763), None);
+
+        // 1.20.2+
+        assert_eq!(uuid(&login_start(&UUID.to_be_bytes()), 766), expected);
+        assert!(login_start(&[]).uuid(766).is_err());
+    }
+
+    #[test]
+    fn transfer_handshake() {
+        let mut packet = BytesMut::new();
+        VarInt(766).serialize(&mut packet);
+        "mc.example.com".serialize(&mut packet);
+        packet.put_u16(25565);
+        packet.put_u8(3);
+
+        let handshake = Handshake::deserialize(packet.freeze()).unwrap();
+        assert!(matches!(handshake.next_state, State::Transfer));
+
+        // primers rewriting the handshake keep the intent
+        let mut handshake = NewHandshake::from(handshake);
+        handshake.server_address.push_str("\x00forwarded");
+
+        let mut rewritten = BytesMut::new();
+        handshake.serialize(&mut rewritten);
+        assert_eq!(rewritten.last(), Some(&3));
+    }
+}
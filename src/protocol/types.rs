@@ -1,5 +1,11 @@
 use std::fmt::Display;
 
+use bytes::Buf;
+use netherite::{DeError, Deserialize};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
 pub struct PlayerUuid(uuid::Uuid);
 
 impl PlayerUuid {
@@ -15,6 +21,10 @@ impl PlayerUuid {
 
         PlayerUuid(uuid)
     }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        self.0.as_bytes()
+    }
 }
 
 impl Display for PlayerUuid {
@@ -22,3 +32,40 @@ impl Display for PlayerUuid {
         write!(f, "{}", self.0)
     }
 }
+
+impl TryFrom<String> for PlayerUuid {
+    type Error = uuid::Error;
+
+    /// parses both hyphenated and simple UUIDs
+    fn try_from(uuid: String) -> Result<Self, Self::Error> {
+        uuid::Uuid::parse_str(&uuid).map(PlayerUuid)
+    }
+}
+
+impl Deserialize for PlayerUuid {
+    fn deserialize(mut buffer: impl Buf) -> Result<Self, DeError> {
+        let most = u64::deserialize(&mut buffer)?;
+        let least = u64::deserialize(&mut buffer)?;
+
+        Ok(PlayerUuid(uuid::Uuid::from_u64_pair(most, least)))
+    }
+}
+
+/// Property of a player's profile, such as its skin (`textures`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, serde::Deserialize)]
+pub struct Property {
+    pub name: String,
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl Property {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            signature: None,
+        }
+    }
+}
@@ -1,18 +1,46 @@
-use std::{net::SocketAddr, sync::Arc};
+use arc_swap::ArcSwap;
+use socket2::SockRef;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::net::{TcpListener, TcpStream};
 
-mod backend;
+pub mod accept;
+mod auth;
+pub mod backend;
 pub mod bridge;
+pub mod cidr;
 pub mod client;
+mod ingress;
+pub mod listener;
+pub mod redirect;
 pub mod router;
+pub mod socket;
+pub mod status;
+pub mod stream;
+mod tracker;
 
 pub use crate::HopperError;
 use crate::{
     metrics::{injector::MetricsInjector, EventType, Metrics},
-    server::{backend::Backend, bridge::Bridge},
+    protocol::{login::SessionServer, packet_impls::StatusJson},
+    server::{
+        accept::{Acceptor, LogLimiter},
+        backend::Backend,
+        bridge::{forwarding::ForwardingOptions, Bridge, StatusQuery},
+        client::NextState,
+        router::RouterError,
+        tracker::ConnectionTracker,
+    },
 };
 pub use client::IncomingClient;
-pub use router::Router;
+pub use listener::Listener;
+pub use router::{Route, Router, SwappableRouter};
 
 macro_rules! try_client {
     ($v:expr, $client:expr, $message:tt) => {
@@ -30,48 +58,179 @@ macro_rules! try_client {
 
 pub struct Hopper {
     metrics: Arc<Metrics>,
-    router: Arc<dyn Router>,
+    connections: Arc<ConnectionTracker>,
+
+    /// connections above this are closed as soon as they're accepted
+    max_connections: AtomicUsize,
+    forwarding: ArcSwap<ForwardingOptions>,
+    session: ArcSwap<SessionServer>,
 }
 
 impl Hopper {
-    pub fn new(router: Arc<dyn Router>, injector: Box<dyn MetricsInjector>) -> Self {
+    pub fn new(injector: Box<dyn MetricsInjector>) -> Self {
         Self {
-            router,
             metrics: Arc::new(Metrics::init(injector)),
+            connections: Default::default(),
+            max_connections: AtomicUsize::new(usize::MAX),
+            forwarding: Default::default(),
+            session: Default::default(),
         }
     }
 
+    /// secrets and profiles used by the forwarding strategies of new connections
+    pub fn set_forwarding(&self, forwarding: ForwardingOptions) {
+        self.forwarding.store(Arc::new(forwarding));
+    }
+
+    /// session server players logging in to online-mode routes are checked with
+    pub fn set_session_server(&self, session: SessionServer) {
+        self.session.store(Arc::new(session));
+    }
+
+    /// caps the number of connections handled at once, None to remove the cap
+    pub fn set_max_connections(&self, max: Option<usize>) {
+        self.max_connections
+            .store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// number of connections currently being handled
+    pub fn active_connections(&self) -> usize {
+        self.connections.active()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     pub async fn handler(
         client: (TcpStream, SocketAddr),
-        router: Arc<dyn Router>,
+        router: Arc<Listener>,
         metrics: Arc<Metrics>,
+        forwarding: Arc<ForwardingOptions>,
+        session: Arc<SessionServer>,
     ) -> Result<(), HopperError> {
+        if router.proxy_protocol() && !router.trusts(client.1.ip()) {
+            return Err(HopperError::Untrusted(client.1));
+        }
+
+        router
+            .socket()
+            .apply(SockRef::from(&client.0), client.1.is_ipv6())
+            .map_err(HopperError::SocketOptions)?;
+
         // receives a handshake from the client and decodes its information
-        let mut client = IncomingClient::init(client).await?;
+        let mut client = IncomingClient::init(client, router.proxy_protocol()).await?;
 
         // routes a client by reading handshake information
         // then if a route has been found it connects to the server
         // but does not yet send handshaking information
-        let route = try_client!(
-            router.route(&mut client),
+        let route = router.route(&mut client);
+
+        // pings of hostnames that can't be routed may get an explanation
+        if let (Err(err), NextState::Status) = (&route, &client.next_state) {
+            if let Some(status) = router.unknown_status(client.handshake.protocol_version.0) {
+                log::debug!("Answering the ping of {client} which can't be routed: {err}");
+                return Self::answer(client, &status, &metrics).await;
+            }
+        }
+
+        let route = try_client!(route, client, "Couldn't route {client}: {}");
+
+        let route = match route {
+            Route::Backend(destination) => destination,
+            Route::Redirect(redirect) => {
+                log::info!("redirecting {} to {redirect}", client.address);
+                return redirect.send(client).await;
+            }
+        };
+
+        // the route may override the options of the listener
+        let socket = client.connection.socket().tcp();
+        if let Some(socket) = socket.filter(|_| route.socket() != router.socket()) {
+            let ipv6 = socket.peer_addr().is_ok_and(|addr| addr.is_ipv6());
+
+            try_client!(
+                route
+                    .socket()
+                    .apply(SockRef::from(socket), ipv6)
+                    .map_err(HopperError::SocketOptions),
+                client,
+                "Cannot set socket options of {client}: {}"
+            );
+        }
+
+        try_client!(
+            forwarding.check(route.strategy()),
             client,
-            "Couldn't route {client}: {}"
+            "Cannot forward {client}: {}"
         );
 
+        // pings may be answered with the status the backend last sent
+        let cache = match client.next_state {
+            NextState::Status => route.status_cache().cloned(),
+            _ => None,
+        };
+
         let route_addr = route.address();
+
+        if let Some(cache) = cache {
+            let protocol = client.handshake.protocol_version.0;
+            let query = || {
+                let (query, route) = (StatusQuery::new(&client, &route), route.clone());
+                async move { query.send(Backend::connect(&route).await?).await }
+            };
+
+            let status = match (cache.get(protocol, query).await, route.offline()) {
+                (Some(status), _) => Ok(status),
+                (None, Some(offline)) => Ok(Arc::new(offline.status().json(protocol))),
+                (None, None) => Err(HopperError::Router(RouterError::Unavailable)),
+            };
+
+            let status = try_client!(status, client, "Cannot get the status of {route_addr}: {}");
+
+            return Self::answer(client, &status, &metrics).await;
+        }
+
         log::info!("connecting {} to {route_addr}", client.address);
 
+        let backend = Backend::connect(&route).await;
+
+        // the route may tell clients its backend is offline
+        if let (Err(err), Some(offline)) = (&backend, route.offline()) {
+            log::debug!("Cannot connect {client} to {route_addr}: {err}");
+
+            return match client.next_state {
+                NextState::Status => {
+                    let status = offline.status().json(client.handshake.protocol_version.0);
+                    Self::answer(client, &status, &metrics).await
+                }
+                NextState::Login(_) | NextState::Transfer(_) => {
+                    client.disconnect(offline.kick()).await;
+                    Ok(())
+                }
+            };
+        }
+
         let backend = try_client!(
-            Backend::connect(&route).await,
+            backend,
             client,
             "Cannot connect {client} to {route_addr}: {}"
         );
 
+        // players are only authenticated once their backend is reachable,
+        // the connection waits for the login to be forwarded
+        if route.online_mode() {
+            let address = client.address;
+            client = auth::authenticate(client, &session)
+                .await
+                .inspect_err(|err| log::info!("Cannot authenticate {address}: {err}"))?;
+        }
+
         // create a metricsguard which contains a channel where
         // events are sent, and then added to the metrics state
         let guard = metrics.guard(client.hostname.clone(), client.handshake.next_state);
 
-        let bridge = Bridge::new(backend, client, route.strategy());
+        let bridge = Bridge::new(backend, client, &route, forwarding);
 
         // bridge returns the used traffic in form of bytes
         // transited from client to server and vice versa
@@ -94,22 +253,64 @@ impl Hopper {
         Ok(())
     }
 
-    pub async fn listen(&self, listener: TcpListener) -> ! {
-        log::info!("Listening on {}", listener.local_addr().unwrap());
+    /// answers a ping without reaching the backend, still counted as a connection
+    async fn answer(
+        client: IncomingClient,
+        status: &StatusJson,
+        metrics: &Metrics,
+    ) -> Result<(), HopperError> {
+        let guard = metrics.guard(client.hostname.clone(), client.handshake.next_state);
+
+        guard.send_event(EventType::Connect).await;
+        let result = client.respond_status(status).await;
+        guard.send_event(EventType::Disconnect).await;
+
+        result
+    }
+
+    /// accepts connections from `socket`, routing them with
+    /// whatever `listener` currently holds
+    pub async fn listen(&self, socket: &TcpListener, listener: &SwappableRouter<Listener>) -> ! {
+        let mut acceptor = Acceptor::new(socket);
+        let mut rejected = LogLimiter::default();
 
         loop {
-            let client = listener.accept().await.unwrap();
+            let client = acceptor.accept(self.metrics.accept()).await;
+
+            // shed load by closing the connection right away,
+            // instead of leaving it waiting in the backlog
+            let max = self.max_connections.load(Ordering::Relaxed);
+            if self.active_connections() >= max {
+                self.metrics.accept().reject();
+
+                if let Some(suppressed) = rejected.allow() {
+                    log::warn!(
+                        "Reached the limit of {max} connections, rejecting {} ({suppressed} more rejected)",
+                        client.1
+                    );
+                }
+
+                drop(client);
+                tokio::task::yield_now().await;
+                continue;
+            }
 
             // cheap to clone but it'd be better to clone only if needed
             // TODO: clone only when needed
-            let router = self.router.clone();
+            let router = listener.load();
             let metrics = self.metrics.clone();
+            let forwarding = self.forwarding.load_full();
+            let session = self.session.load_full();
+            let guard = self.connections.track();
 
             // creates a new task for each client
             tokio::spawn(async move {
-                if let Err(err) = Self::handler(client, router, metrics).await {
+                if let Err(err) = Self::handler(client, router, metrics, forwarding, session).await
+                {
                     log::debug!("{}", err)
                 };
+
+                drop(guard);
             });
 
             // yield execution to the executor
@@ -118,4 +319,70 @@ impl Hopper {
             tokio::task::yield_now().await
         }
     }
+
+    /// keeps accepting connections without routing them, answering
+    /// status requests and kicking players with `message`
+    pub async fn refuse(&self, socket: &TcpListener, listener: Arc<Listener>, message: &str) -> ! {
+        let message: Arc<str> = message.into();
+        let mut acceptor = Acceptor::new(socket);
+
+        loop {
+            let client = acceptor.accept(self.metrics.accept()).await;
+            let message = message.clone();
+            let proxy_protocol = listener.proxy_protocol();
+
+            if proxy_protocol && !listener.trusts(client.1.ip()) {
+                log::debug!("{}", HopperError::Untrusted(client.1));
+                continue;
+            }
+
+            tokio::spawn(async move {
+                let client = match IncomingClient::init(client, proxy_protocol).await {
+                    Ok(client) => client,
+                    Err(err) => {
+                        log::debug!("{}", err);
+                        return;
+                    }
+                };
+
+                match client.next_state {
+                    NextState::Status => {
+                        let protocol = client.handshake.protocol_version.0;
+                        let status = StatusJson::new("Hopper", protocol, &message);
+
+                        if let Err(err) = client.respond_status(&status).await {
+                            log::debug!("{}", err)
+                        }
+                    }
+                    NextState::Login(_) | NextState::Transfer(_) => {
+                        client.disconnect(&*message).await
+                    }
+                }
+            });
+
+            tokio::task::yield_now().await
+        }
+    }
+
+    /// waits up to `timeout` for every connection to terminate,
+    /// returning the number of connections still open
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        let mut report = tokio::time::interval(Duration::from_secs(5));
+        report.tick().await;
+
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+
+        loop {
+            tokio::select! {
+                _ = self.connections.drained() => break,
+                _ = &mut deadline => break,
+                _ = report.tick() => {
+                    log::info!("Waiting for {} connections to close", self.active_connections())
+                }
+            }
+        }
+
+        self.active_connections()
+    }
 }
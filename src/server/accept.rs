@@ -0,0 +1,176 @@
+//! Accepting connections without letting errors take the listener down.
+//! Errors are classified to decide whether to retry right away, back off
+//! or stop accepting until file descriptors are freed
+
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::metrics::AcceptCounters;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// minimum time between two logs of the same kind
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptError {
+    /// the pending connection failed before being accepted,
+    /// only that connection is affected
+    Aborted,
+
+    /// the process or the system ran out of file descriptors or memory
+    Exhausted,
+
+    /// any other error, which may go away by itself
+    Transient,
+}
+
+impl AcceptError {
+    pub fn classify(err: &io::Error) -> Self {
+        #[cfg(unix)]
+        match err.raw_os_error() {
+            Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) => {
+                return Self::Exhausted
+            }
+            // EPERM is returned on linux when a firewall rule blocks the connection
+            Some(libc::ECONNABORTED | libc::EPROTO | libc::EPERM) => return Self::Aborted,
+            _ => {}
+        }
+
+        match err.kind() {
+            io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock => Self::Aborted,
+            io::ErrorKind::OutOfMemory => Self::Exhausted,
+            _ => Self::Transient,
+        }
+    }
+}
+
+/// Lets a message through at most once every [`LOG_INTERVAL`]
+#[derive(Default)]
+pub struct LogLimiter {
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+impl LogLimiter {
+    /// returns the number of messages suppressed since the last
+    /// one if this one can be logged, None otherwise
+    pub fn allow(&mut self) -> Option<u64> {
+        let now = Instant::now();
+
+        match self.last {
+            Some(last) if now.duration_since(last) < LOG_INTERVAL => {
+                self.suppressed += 1;
+                None
+            }
+            _ => {
+                self.last = Some(now);
+                Some(std::mem::take(&mut self.suppressed))
+            }
+        }
+    }
+}
+
+/// Accepts connections from a socket, backing off on errors
+pub struct Acceptor<'a> {
+    socket: &'a TcpListener,
+    backoff: Duration,
+    log: LogLimiter,
+}
+
+impl<'a> Acceptor<'a> {
+    pub fn new(socket: &'a TcpListener) -> Self {
+        Self {
+            socket,
+            backoff: MIN_BACKOFF,
+            log: LogLimiter::default(),
+        }
+    }
+
+    /// waits for the next connection, recording errors in `counters`
+    pub async fn accept(&mut self, counters: &AcceptCounters) -> (TcpStream, SocketAddr) {
+        loop {
+            let err = match self.socket.accept().await {
+                Ok(client) => {
+                    self.backoff = MIN_BACKOFF;
+                    return client;
+                }
+                Err(err) => err,
+            };
+
+            let kind = AcceptError::classify(&err);
+            counters.record(kind);
+
+            let suppressed = match kind {
+                AcceptError::Aborted => {
+                    log::debug!("Connection aborted before being accepted: {err}");
+                    continue;
+                }
+                _ => self.log.allow(),
+            };
+
+            let address = self.socket.local_addr().ok();
+            let address = address.as_ref().map_or("?".into(), ToString::to_string);
+
+            match (kind, suppressed) {
+                (AcceptError::Exhausted, Some(suppressed)) => log::error!(
+                    "Out of resources accepting on {address}, pausing accepts for {:?}: {err} ({suppressed} more errors)",
+                    self.backoff
+                ),
+                (_, Some(suppressed)) => log::warn!(
+                    "Cannot accept on {address}, retrying in {:?}: {err} ({suppressed} more errors)",
+                    self.backoff
+                ),
+                _ => {}
+            }
+
+            // pending connections wait in the backlog, or get
+            // refused by the kernel once it's full
+            tokio::time::sleep(self.backoff).await;
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::{AcceptError, LogLimiter};
+
+    #[test]
+    fn classify() {
+        let aborted = io::Error::from(io::ErrorKind::ConnectionAborted);
+        assert_eq!(AcceptError::classify(&aborted), AcceptError::Aborted);
+
+        #[cfg(unix)]
+        {
+            let emfile = io::Error::from_raw_os_error(libc::EMFILE);
+            assert_eq!(AcceptError::classify(&emfile), AcceptError::Exhausted);
+
+            let eproto = io::Error::from_raw_os_error(libc::EPROTO);
+            assert_eq!(AcceptError::classify(&eproto), AcceptError::Aborted);
+        }
+
+        let other = io::Error::from(io::ErrorKind::Other);
+        assert_eq!(AcceptError::classify(&other), AcceptError::Transient);
+    }
+
+    #[test]
+    fn limiter() {
+        let mut limiter = LogLimiter::default();
+        assert_eq!(limiter.allow(), Some(0));
+        assert_eq!(limiter.allow(), None);
+        assert_eq!(limiter.allow(), None);
+        assert_eq!(limiter.suppressed, 2);
+    }
+}
@@ -0,0 +1,276 @@
+//! Online-mode logins performed by hopper, so that offline-mode
+//! backends receive identities verified by the session server
+
+use std::{sync::OnceLock, time::Duration};
+
+use crate::{
+    protocol::{
+        connection::Connection,
+        login::{self, KeyPair, LoginError, SessionServer},
+        packet::ProtoError,
+    },
+    HopperError,
+};
+
+use super::IncomingClient;
+
+/// time clients have to join through the session server
+/// and answer the encryption request
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// key pair of the encryption requests, generated on the first login
+async fn keys() -> Result<&'static KeyPair, LoginError> {
+    static KEYS: OnceLock<KeyPair> = OnceLock::new();
+
+    if let Some(keys) = KEYS.get() {
+        return Ok(keys);
+    }
+
+    // the first logins may each generate a pair, only one is kept
+    let keys = tokio::task::spawn_blocking(KeyPair::generate).await??;
+    Ok(KEYS.get_or_init(|| keys))
+}
+
+/// encrypts the connection of a player logging in and checks with
+/// `session` that it joined. Players failing to do so are disconnected,
+/// status connections are returned untouched
+pub async fn authenticate(
+    mut client: IncomingClient,
+    session: &SessionServer,
+) -> Result<IncomingClient, HopperError> {
+    let protocol = client.handshake.protocol_version.0;
+    let (username, chat_key) = match client.next_state.login() {
+        Some(login) => {
+            let login = login.data()?;
+            let chat_key = login.chat_key(protocol).map_err(ProtoError::from)?;
+            (login.username.clone(), chat_key)
+        }
+        None => return Ok(client),
+    };
+
+    let keys = keys().await?;
+
+    let exchange = login::exchange(&mut client.connection, keys, protocol, chat_key.as_deref());
+    let secret = match tokio::time::timeout(LOGIN_TIMEOUT, exchange).await {
+        Ok(Ok(secret)) => secret,
+        Ok(Err(err)) => {
+            client.disconnect_err(&err).await;
+            return Err(err.into());
+        }
+        Err(_) => return Err(HopperError::TimeOut),
+    };
+
+    // everything from now on is encrypted, both ways
+    let (stream, mut read) = client.connection.into_parts();
+    let stream = stream
+        .encrypt(&secret, &mut read)
+        .map_err(|_| LoginError::Unencryptable)?;
+    client.connection = Connection::with_buffer(stream, read);
+
+    let server_hash = login::server_hash(&secret, keys);
+    match session.has_joined(&username, &server_hash).await {
+        Ok(profile) => {
+            client.profile = Some(profile);
+            Ok(client)
+        }
+        Err(err) => {
+            client.disconnect_err(&err).await;
+            Err(err.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::{Buf, BufMut, BytesMut};
+    use netherite::{encoding::str::Str, packet::RawPacket, Deserialize, Serialize};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{authenticate, keys};
+    use crate::{
+        protocol::{
+            connection::Connection,
+            login::{self, LoginError, SessionServer},
+        },
+        server::{
+            client::test::{login, login_with},
+            stream::Stream,
+        },
+        HopperError,
+    };
+    use rsa::{
+        pkcs1v15::SigningKey,
+        pkcs8::EncodePublicKey,
+        rand_core::OsRng,
+        sha2::Sha256,
+        signature::{SignatureEncoding, Signer},
+        RsaPrivateKey,
+    };
+
+    const SECRET: [u8; 16] = *b"sixteen byte key";
+
+    /// session server which only knows Notch, joined with `SECRET`
+    async fn session_server() -> SessionServer {
+        let server_hash = login::server_hash(&SECRET, keys().await.unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..read]);
+
+                let query = format!(
+                    "GET /session/minecraft/hasJoined?username=Notch&serverId={server_hash} "
+                );
+                let response = match request.starts_with(&query) {
+                    true => {
+                        let body = r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch","properties":[{"name":"textures","value":"skin","signature":"signed"}]}"#;
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                            body.len()
+                        )
+                    }
+                    false => "HTTP/1.1 204 No Content\r\n\r\n".into(),
+                };
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        SessionServer::new(format!("http://{address}/"))
+    }
+
+    /// answers the encryption request as a client, encrypting the connection
+    async fn encrypt(mut player: Connection) -> Connection<Stream> {
+        let request = player.read_packet().await.unwrap();
+        assert_eq!(request.packet_id, 0x01);
+
+        let mut data = request.data;
+        assert_eq!(&*Str::deserialize(&mut data).unwrap(), "");
+        let public_key = bytes::Bytes::deserialize(&mut data).unwrap();
+        let verify_token = bytes::Bytes::deserialize(&mut data).unwrap();
+        assert_eq!(data.get_u8(), 1);
+
+        let keys = keys().await.unwrap();
+        assert_eq!(&public_key[..], keys.public_key());
+
+        let mut response = BytesMut::new();
+        (&keys.encrypt(&SECRET)[..]).serialize(&mut response);
+        (&keys.encrypt(&verify_token)[..]).serialize(&mut response);
+
+        let response = RawPacket {
+            packet_id: 0x01,
+            data: response.freeze(),
+        };
+        player.feed_raw(&response).await.unwrap();
+        player.flush().await.unwrap();
+
+        let (stream, mut read) = player.into_parts();
+        let stream = Stream::from(stream).encrypt(&SECRET, &mut read);
+        Connection::with_buffer(stream.ok().unwrap(), read)
+    }
+
+    #[tokio::test]
+    async fn online_login() {
+        let session = session_server().await;
+        let (player, client) = login(766, "mc.example.com", "Notch", 0).await;
+
+        let hopper = tokio::spawn(async move { authenticate(client, &session).await });
+        let mut player = encrypt(player).await;
+
+        let client = hopper.await.unwrap().unwrap();
+        let profile = client.profile.as_ref().unwrap();
+        assert_eq!(
+            profile.id.to_string(),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+        assert_eq!(profile.properties[0].signature.as_deref(), Some("signed"));
+
+        // hopper keeps talking to the player encrypted
+        client.disconnect("encrypted").await;
+        let disconnect = player.read_packet().await.unwrap();
+        let reason = Str::deserialize(disconnect.data).unwrap();
+        assert!(reason.contains("encrypted"));
+    }
+
+    /// logs in as a 1.19.2 client signing the verify token with its
+    /// chat key, or with another key when `forged`
+    async fn signed_login(forged: bool) -> Result<(), HopperError> {
+        let session = session_server().await;
+        let chat_key = RsaPrivateKey::new(&mut OsRng, 512).unwrap();
+
+        let mut login_start = BytesMut::new();
+        login_start.put_u8(1);
+        login_start.put_i64(0);
+        (chat_key
+            .to_public_key()
+            .to_public_key_der()
+            .unwrap()
+            .as_bytes())
+        .serialize(&mut login_start);
+        (&b"signed by mojang"[..]).serialize(&mut login_start);
+        login_start.put_u8(0);
+
+        let (mut player, client) = login_with(760, "mc.example.com", "Notch", &login_start).await;
+        let hopper = tokio::spawn(async move { authenticate(client, &session).await });
+
+        let mut request = player.read_packet().await.unwrap().data;
+        Str::deserialize(&mut request).unwrap();
+        bytes::Bytes::deserialize(&mut request).unwrap();
+        let verify_token = bytes::Bytes::deserialize(&mut request).unwrap();
+
+        let signer = match forged {
+            false => chat_key,
+            true => RsaPrivateKey::new(&mut OsRng, 512).unwrap(),
+        };
+        let mut signed = verify_token.to_vec();
+        signed.extend_from_slice(&42i64.to_be_bytes());
+        let signature = SigningKey::<Sha256>::new(signer).sign(&signed).to_vec();
+
+        let mut response = BytesMut::new();
+        (&keys().await.unwrap().encrypt(&SECRET)[..]).serialize(&mut response);
+        response.put_u8(0);
+        response.put_i64(42);
+        (&signature[..]).serialize(&mut response);
+
+        let response = RawPacket {
+            packet_id: 0x01,
+            data: response.freeze(),
+        };
+        player.feed_raw(&response).await.unwrap();
+        player.flush().await.unwrap();
+
+        hopper.await.unwrap().map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn signed_verify_token() {
+        signed_login(false).await.unwrap();
+
+        let forged = signed_login(true).await;
+        assert!(matches!(
+            forged,
+            Err(HopperError::Login(LoginError::Signature))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unverified() {
+        let session = session_server().await;
+        let (player, client) = login(766, "mc.example.com", "jeb_", 0).await;
+
+        let hopper = tokio::spawn(async move { authenticate(client, &session).await });
+        let mut player = encrypt(player).await;
+
+        assert!(hopper.await.unwrap().is_err());
+        let disconnect = player.read_packet().await.unwrap();
+        let reason = Str::deserialize(disconnect.data).unwrap();
+        assert!(reason.contains("Failed to verify username!"));
+    }
+}
@@ -1,14 +1,27 @@
 //! Destination server
 
-use std::marker::PhantomData;
-use tokio::net::TcpStream;
+use socket2::SockRef;
+#[cfg(unix)]
+use std::path::Path;
+use std::{
+    fmt::Display,
+    io,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::TcpSocket;
+#[cfg(unix)]
+use tokio::net::UnixStream;
 
-use super::{bridge::forwarding::ConnectionPrimer, router::Destination};
+use super::{bridge::forwarding::ConnectionPrimer, router::Destination, stream::Stream};
 use crate::{
     protocol::{connection::Connection, packet::DecodedPacket, packet_impls::Handshake},
     HopperError,
 };
 
+pub mod proxy;
+
 pub trait BackendState {}
 
 pub struct Connected(());
@@ -16,21 +29,154 @@ impl BackendState for Connected {}
 pub struct Primed(());
 impl BackendState for Primed {}
 
+/// Address of a backend, reachable over TCP or through a unix socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendAddr {
+    Inet(SocketAddr),
+    /// hostname resolved by the outbound proxy of the route
+    Named {
+        host: Arc<str>,
+        port: u16,
+    },
+    #[cfg(unix)]
+    Unix(Arc<Path>),
+}
+
+impl BackendAddr {
+    /// the TCP address, None for unix sockets
+    pub fn inet(&self) -> Option<SocketAddr> {
+        match *self {
+            BackendAddr::Inet(address) => Some(address),
+            _ => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for BackendAddr {
+    fn from(address: SocketAddr) -> Self {
+        Self::Inet(address)
+    }
+}
+
+impl Display for BackendAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendAddr::Inet(address) => address.fmt(f),
+            BackendAddr::Named { host, port } => write!(f, "{host}:{port}"),
+            #[cfg(unix)]
+            BackendAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
 pub struct Backend<S: BackendState> {
-    stream: Connection,
+    stream: Connection<Stream>,
     _state: PhantomData<S>,
 }
 
-impl Backend<Connected> {
-    pub async fn connect(destination: &Destination) -> Result<Self, HopperError> {
-        let stream = tokio::time::timeout(
-            std::time::Duration::from_secs(2),
-            TcpStream::connect(destination.address()),
-        )
+/// binds `socket` to a local `source` address, leaving the port to be chosen
+/// when connecting so that it only needs to be unique per destination
+fn bind_source(socket: &TcpSocket, source: IpAddr) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let enable: libc::c_int = 1;
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_BIND_ADDRESS_NO_PORT,
+                &enable as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&enable) as libc::socklen_t,
+            )
+        };
+
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    socket.bind(SocketAddr::new(source, 0))
+}
+
+/// connects to `target` over TCP, through the proxy of `destination` if any
+async fn connect_tcp(
+    destination: &Destination,
+    target: &BackendAddr,
+) -> Result<Stream, HopperError> {
+    // with a proxy the socket connects to it instead
+    let remote = match (destination.proxy(), target) {
+        (Some(proxy), _) => proxy.address(),
+        (None, BackendAddr::Inet(address)) => *address,
+        (None, _) => {
+            let err = io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only proxies resolve the hostnames of backends",
+            );
+
+            return Err(HopperError::Connect(err));
+        }
+    };
+    let socket = match remote {
+        SocketAddr::V4(_) => TcpSocket::new_v4(),
+        SocketAddr::V6(_) => TcpSocket::new_v6(),
+    }
+    .map_err(HopperError::Connect)?;
+
+    // set before connecting, buffer sizes affect the window scale
+    destination
+        .socket()
+        .apply(SockRef::from(&socket), remote.is_ipv6())
+        .map_err(HopperError::SocketOptions)?;
+
+    if let Some(source) = destination.source() {
+        bind_source(&socket, source).map_err(HopperError::Connect)?;
+    }
+
+    let mut stream = socket.connect(remote).await.map_err(HopperError::Connect)?;
+    if let Some(proxy) = destination.proxy() {
+        proxy
+            .tunnel(&mut stream, target)
+            .await
+            .map_err(HopperError::Connect)?;
+    }
+
+    Ok(stream.into())
+}
+
+#[cfg(unix)]
+async fn connect_unix(destination: &Destination, path: &Path) -> Result<Stream, HopperError> {
+    if destination.proxy().is_some() {
+        let err = io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix sockets cannot be reached through a proxy",
+        );
+
+        return Err(HopperError::Connect(err));
+    }
+
+    let stream = UnixStream::connect(path)
         .await
-        .map_err(|_| HopperError::TimeOut)?
         .map_err(HopperError::Connect)?;
 
+    Ok(stream.into())
+}
+
+impl Backend<Connected> {
+    pub async fn connect(destination: &Destination) -> Result<Self, HopperError> {
+        let connect = async {
+            match destination.address() {
+                #[cfg(unix)]
+                BackendAddr::Unix(path) => connect_unix(destination, path).await,
+                target => connect_tcp(destination, target).await,
+            }
+        };
+
+        let stream = tokio::time::timeout(std::time::Duration::from_secs(2), connect)
+            .await
+            .map_err(|_| HopperError::TimeOut)??;
+
         let stream = Connection::new(stream);
 
         Ok(Backend {
@@ -54,7 +200,7 @@ impl Backend<Connected> {
 }
 
 impl Backend<Primed> {
-    pub fn into_inner(self) -> Connection {
+    pub fn into_inner(self) -> Connection<Stream> {
         self.stream
     }
 }
@@ -1,16 +1,29 @@
 pub mod forwarding;
 mod piping;
+pub mod profiles;
 
-use crate::HopperError;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::{
+    protocol::{
+        packet::{DecodedPacket, ProtoError},
+        packet_impls::{Handshake, StatusJson, StatusRequest},
+    },
+    HopperError,
+};
 
 use self::{
-    forwarding::{BungeeCord, ForwardStrategy, Passthrough, ProxyProtocol, RealIP},
+    forwarding::{
+        BungeeCord, ForwardStrategy, ForwardingOptions, Passthrough, ProxyProtocol, ProxyVersion,
+        RealIP, Velocity,
+    },
     piping::{copy_bidirectional, flush_bidirectional},
 };
 
 use super::{
     backend::{Backend, Connected},
-    client::{IncomingClient, NextState},
+    client::{Hostname, IncomingClient},
+    router::Destination,
 };
 
 pub struct Bridge {
@@ -19,18 +32,31 @@ pub struct Bridge {
     server: Backend<Connected>,
     client: IncomingClient,
     forwarding: ForwardStrategy,
+    /// send TLVs along the PROXY protocol header
+    tlvs: bool,
+    bungeeguard: Option<Arc<str>>,
+    /// forward the unverified UUID sent by the client
+    client_uuid: bool,
+    options: Arc<ForwardingOptions>,
 }
 
 impl Bridge {
+    /// bridges `client` to `server`, forwarding
+    /// its information as set by `route`
     pub fn new(
         server: Backend<Connected>,
         client: IncomingClient,
-        forwarding: ForwardStrategy,
+        route: &Destination,
+        options: Arc<ForwardingOptions>,
     ) -> Self {
         Self {
             server,
             client,
-            forwarding,
+            forwarding: route.strategy(),
+            tlvs: route.tlvs(),
+            bungeeguard: route.bungeeguard().cloned(),
+            client_uuid: route.client_uuid(),
+            options,
         }
     }
 
@@ -41,23 +67,71 @@ impl Bridge {
     /// Returns the number of bytes transferred between
     /// the client and the server. Tuple is (serverbound, clientbound)
     pub async fn bridge(mut self) -> Result<(u64, u64), HopperError> {
-        let server = match (&mut self.client.next_state, self.forwarding) {
-            (NextState::Login(login), ForwardStrategy::BungeeCord) => {
+        // velocity forwarding data is only sent after login start
+        let mut velocity = None;
+
+        let server = match (self.client.next_state.login(), self.forwarding) {
+            (Some(login), ForwardStrategy::BungeeCord) => {
                 // bungeecord forwarding requires username
                 // for UUID calculation
 
+                let protocol = self.client.handshake.protocol_version.0;
                 let login_start = login.data()?;
+
                 let primer = BungeeCord::from_username(self.client.address, &login_start.username);
+                let primer = match self.client.profile {
+                    Some(ref profile) => primer.with_profile(profile),
+                    None => {
+                        // offline clients can claim any UUID, only trusted when asked to
+                        let primer = match self.client_uuid {
+                            true => {
+                                let uuid = login_start.uuid(protocol).map_err(ProtoError::from)?;
+                                primer.with_uuid(uuid)
+                            }
+                            false => primer,
+                        };
+
+                        let properties = self
+                            .options
+                            .profiles
+                            .get(primer.uuid(), &login_start.username);
+                        primer.with_properties(properties)
+                    }
+                };
+                let primer = primer.with_token(self.bungeeguard);
 
                 self.server.prime(primer, self.client.handshake).await?
             }
+            (Some(login), ForwardStrategy::Velocity) => {
+                let secret = self.options.velocity.clone();
+                let secret = secret.ok_or(HopperError::NoSecret("velocity"))?;
+
+                let login_start = login.data()?;
+                let primer = Velocity::new(self.client.address, &login_start.username, secret);
+                velocity = Some(primer.with_profile(self.client.profile.as_ref()));
+
+                self.server
+                    .prime(Passthrough, self.client.handshake)
+                    .await?
+            }
             // realip works both for login and ping
             (_, ForwardStrategy::RealIP) => {
                 let primer = RealIP::new(self.client.address);
                 self.server.prime(primer, self.client.handshake).await?
             }
             (_, ForwardStrategy::ProxyProtocol) => {
-                let primer = ProxyProtocol::new(self.client.address);
+                let (client, destination) = (self.client.address, self.client.destination);
+                let primer = ProxyProtocol::new(client, destination, ProxyVersion::V2);
+                let primer = match self.tlvs {
+                    true => primer.with_tlvs(&self.client.hostname),
+                    false => primer,
+                };
+
+                self.server.prime(primer, self.client.handshake).await?
+            }
+            (_, ForwardStrategy::ProxyProtocolV1) => {
+                let (client, destination) = (self.client.address, self.client.destination);
+                let primer = ProxyProtocol::new(client, destination, ProxyVersion::V1);
                 self.server.prime(primer, self.client.handshake).await?
             }
             // default handler does not forward anything
@@ -68,13 +142,18 @@ impl Bridge {
             }
         };
 
-        let client = self.client.connection;
+        let mut client = self.client.connection;
         let mut server = server.into_inner();
 
         // if the NextState is login the login packet has been read too.
         // Send it to the server as is.
-        if let NextState::Login(ref login) = self.client.next_state {
-            server.feed_raw_packet(login).await?;
+        if let Some(login) = self.client.next_state.login() {
+            server.feed_raw_packet(&*login).await?;
+        }
+
+        if let Some(velocity) = velocity {
+            server.flush().await?;
+            velocity.answer(&mut server, &mut client).await?;
         }
 
         let (client, server) = flush_bidirectional(client, server).await?;
@@ -84,3 +163,65 @@ impl Bridge {
         Ok(transferred)
     }
 }
+
+/// Status request sent to a backend by hopper on behalf of a client,
+/// so that the response can be cached. Only the strategies
+/// forwarding the address of pinging clients apply
+pub struct StatusQuery {
+    address: SocketAddr,
+    destination: SocketAddr,
+    hostname: Hostname,
+    handshake: DecodedPacket<Handshake>,
+    forwarding: ForwardStrategy,
+    tlvs: bool,
+}
+
+impl StatusQuery {
+    pub fn new(client: &IncomingClient, route: &Destination) -> Self {
+        Self {
+            address: client.address,
+            destination: client.destination,
+            hostname: client.hostname.clone(),
+            handshake: client.handshake.clone(),
+            forwarding: route.strategy(),
+            tlvs: route.tlvs(),
+        }
+    }
+
+    async fn send_inner(self, server: Backend<Connected>) -> Result<StatusJson, HopperError> {
+        let server = match self.forwarding {
+            ForwardStrategy::RealIP => {
+                let primer = RealIP::new(self.address);
+                server.prime(primer, self.handshake).await?
+            }
+            ForwardStrategy::ProxyProtocol => {
+                let primer = ProxyProtocol::new(self.address, self.destination, ProxyVersion::V2);
+                let primer = match self.tlvs {
+                    true => primer.with_tlvs(&self.hostname),
+                    false => primer,
+                };
+
+                server.prime(primer, self.handshake).await?
+            }
+            ForwardStrategy::ProxyProtocolV1 => {
+                let primer = ProxyProtocol::new(self.address, self.destination, ProxyVersion::V1);
+                server.prime(primer, self.handshake).await?
+            }
+            _ => server.prime(Passthrough, self.handshake).await?,
+        };
+
+        let mut server = server.into_inner();
+        server.feed_packet(StatusRequest {}).await?;
+        server.flush().await?;
+
+        let status: DecodedPacket<StatusJson> = server.read_packet().await?.try_into()?;
+        Ok(status.into_data())
+    }
+
+    /// requests the status of `server`, returning the JSON it answered with
+    pub async fn send(self, server: Backend<Connected>) -> Result<StatusJson, HopperError> {
+        tokio::time::timeout(Duration::from_secs(2), self.send_inner(server))
+            .await
+            .map_err(|_| HopperError::TimeOut)?
+    }
+}
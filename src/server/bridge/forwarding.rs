@@ -1,8 +1,19 @@
-use std::{fmt::Write, net::SocketAddr};
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use bytes::BufMut;
+use bytes::{BufMut, BytesMut};
+use netherite::{encoding::varint::VarInt, Serialize};
+use ring::hmac;
 
 use proxy_protocol::{
+    version1,
     version2::{ProxyAddresses, ProxyCommand, ProxyTransportProtocol},
     ProxyHeader,
 };
@@ -11,14 +22,18 @@ use serde::Deserialize;
 use crate::{
     protocol::{
         connection::Connection,
+        login::Profile,
         packet::DecodedPacket,
-        packet_impls::{Handshake, NewHandshake},
-        types::PlayerUuid,
+        packet_impls::{Handshake, LoginPluginRequest, LoginPluginResponse, NewHandshake},
+        types::{PlayerUuid, Property},
     },
+    server::stream::Stream,
     HopperError,
 };
 
-#[derive(Debug, Default, Deserialize, Clone, Copy)]
+use super::profiles::ProfileStore;
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum ForwardStrategy {
     #[default]
     #[serde(rename = "none")]
@@ -33,6 +48,35 @@ pub enum ForwardStrategy {
 
     #[serde(rename = "proxy_protocol")]
     ProxyProtocol,
+
+    /// human readable version of the PROXY protocol
+    #[serde(rename = "proxy_protocol_v1")]
+    ProxyProtocolV1,
+
+    /// velocity modern forwarding, signed with a secret
+    /// shared with the backend
+    #[serde(rename = "velocity")]
+    Velocity,
+}
+
+/// Secrets shared with the backends by the forwarding
+/// strategies needing one, and the profiles they forward
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ForwardingOptions {
+    pub velocity: Option<Arc<[u8]>>,
+    pub profiles: ProfileStore,
+}
+
+impl ForwardingOptions {
+    /// whether the secret needed by `strategy` is configured
+    pub fn check(&self, strategy: ForwardStrategy) -> Result<(), HopperError> {
+        match strategy {
+            ForwardStrategy::Velocity if self.velocity.is_none() => {
+                Err(HopperError::NoSecret("velocity"))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -45,7 +89,7 @@ pub trait ConnectionPrimer {
     /// by the client
     async fn prime_connection(
         self,
-        stream: &mut Connection,
+        stream: &mut Connection<Stream>,
         og_handshake: DecodedPacket<Handshake>,
     ) -> Result<(), HopperError>;
 }
@@ -53,6 +97,9 @@ pub trait ConnectionPrimer {
 pub(super) struct BungeeCord {
     player_addr: SocketAddr,
     player_uuid: PlayerUuid,
+
+    /// profile properties, sent along with the BungeeGuard token
+    properties: Vec<Property>,
 }
 
 impl BungeeCord {
@@ -63,6 +110,51 @@ impl BungeeCord {
             // ignored by online-mode servers so we can always send
             // it even when the server is premium-only
             player_uuid: PlayerUuid::offline_player(player_name),
+            properties: Vec::new(),
+        }
+    }
+
+    /// uses the UUID sent by the client instead of the offline one
+    pub fn with_uuid(self, player_uuid: Option<PlayerUuid>) -> Self {
+        Self {
+            player_uuid: player_uuid.unwrap_or(self.player_uuid),
+            ..self
+        }
+    }
+
+    /// forwards the identity verified by the session server
+    pub fn with_profile(self, profile: &Profile) -> Self {
+        Self {
+            player_uuid: profile.id,
+            ..self
+        }
+        .with_properties(&profile.properties)
+    }
+
+    pub fn with_properties(mut self, properties: &[Property]) -> Self {
+        self.properties.extend_from_slice(properties);
+        self
+    }
+
+    /// adds the token checked by the BungeeGuard plugin to the properties
+    pub fn with_token(mut self, token: Option<Arc<str>>) -> Self {
+        if let Some(token) = token {
+            let property = Property::new("bungeeguard-token", &token);
+            self.properties.push(property);
+        }
+
+        self
+    }
+
+    pub fn uuid(&self) -> &PlayerUuid {
+        &self.player_uuid
+    }
+
+    /// JSON array of profile properties, the optional fourth field
+    fn properties(&self) -> Option<String> {
+        match self.properties.is_empty() {
+            true => None,
+            false => serde_json::to_string(&self.properties).ok(),
         }
     }
 }
@@ -71,7 +163,7 @@ impl BungeeCord {
 impl ConnectionPrimer for BungeeCord {
     async fn prime_connection(
         self,
-        stream: &mut Connection,
+        stream: &mut Connection<Stream>,
         og_handshake: DecodedPacket<Handshake>,
     ) -> Result<(), HopperError> {
         let handshake = og_handshake.into_data();
@@ -94,6 +186,10 @@ impl ConnectionPrimer for BungeeCord {
         )
         .ok();
 
+        if let Some(properties) = self.properties() {
+            write!(handshake.server_address, "\x00{properties}").ok();
+        }
+
         // send the modified handshake
         // packet::write_serialize(handshake, stream).await?;
         stream.feed_packet(handshake).await?;
@@ -116,7 +212,7 @@ impl RealIP {
 impl ConnectionPrimer for RealIP {
     async fn prime_connection(
         self,
-        stream: &mut Connection,
+        stream: &mut Connection<Stream>,
         og_handshake: DecodedPacket<Handshake>,
     ) -> Result<(), HopperError> {
         let Handshake {
@@ -160,22 +256,243 @@ impl ConnectionPrimer for RealIP {
     }
 }
 
+/// channel of the velocity forwarding request
+const VELOCITY_CHANNEL: &str = "velocity:player_info";
+/// MODERN_DEFAULT, the only version without chat signing keys
+const VELOCITY_VERSION: i32 = 1;
+
+/// Velocity modern forwarding. The handshake is sent as-is, the
+/// backend then asks for the player's information during login
+pub(super) struct Velocity {
+    player_addr: SocketAddr,
+    player_name: String,
+    player_uuid: PlayerUuid,
+    properties: Vec<Property>,
+    secret: Arc<[u8]>,
+}
+
+impl Velocity {
+    pub fn new(player_addr: SocketAddr, player_name: &str, secret: Arc<[u8]>) -> Self {
+        Self {
+            player_addr,
+            player_name: player_name.into(),
+            player_uuid: PlayerUuid::offline_player(player_name),
+            properties: Vec::new(),
+            secret,
+        }
+    }
+
+    /// forwards the identity verified by the session server instead
+    pub fn with_profile(self, profile: Option<&Profile>) -> Self {
+        match profile {
+            Some(profile) => Self {
+                player_name: profile.name.clone(),
+                player_uuid: profile.id,
+                properties: profile.properties.clone(),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// forwarding data, HMAC-SHA256 signature followed by the signed payload
+    fn data(&self) -> BytesMut {
+        let mut payload = BytesMut::new();
+        VarInt(VELOCITY_VERSION).serialize(&mut payload);
+        self.player_addr
+            .ip()
+            .to_canonical()
+            .to_string()
+            .serialize(&mut payload);
+        payload.put_slice(self.player_uuid.as_bytes());
+        self.player_name.serialize(&mut payload);
+
+        VarInt(self.properties.len() as i32).serialize(&mut payload);
+        for property in &self.properties {
+            property.name.serialize(&mut payload);
+            property.value.serialize(&mut payload);
+            property.signature.serialize(&mut payload);
+        }
+
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.secret);
+        let mut data = BytesMut::from(hmac::sign(&key, &payload).as_ref());
+        data.put(payload);
+        data
+    }
+
+    /// answers the forwarding request the backend sends after the login
+    /// start packet. Any other packet means the backend doesn't expect
+    /// velocity forwarding, it is relayed to the client which gets to see
+    /// whatever the backend has to say (usually a disconnection)
+    pub async fn answer(
+        self,
+        server: &mut Connection<Stream>,
+        client: &mut Connection<Stream>,
+    ) -> Result<(), HopperError> {
+        let packet = tokio::time::timeout(Duration::from_secs(5), server.read_packet())
+            .await
+            .map_err(|_| HopperError::TimeOut)??;
+
+        let request = match packet.deserialize::<LoginPluginRequest>() {
+            Some(Ok(request)) if &*request.channel == VELOCITY_CHANNEL => request,
+            _ => {
+                log::warn!(
+                    "Backend did not ask for velocity forwarding data, check its configuration"
+                );
+                return Ok(client.feed_raw(&packet).await?);
+            }
+        };
+
+        // a backend asking for a newer version accepts older ones
+        let data = self.data();
+        let response = LoginPluginResponse {
+            message_id: request.message_id,
+            data: Some(&data),
+        };
+
+        server.feed_packet(response).await?;
+        Ok(())
+    }
+}
+
+/// PROXY protocol version sent to the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyVersion {
+    V1,
+    V2,
+}
+
+/// PP2_TYPE_AUTHORITY, the hostname the client connected to
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+/// PP2_TYPE_UNIQUE_ID, an opaque identifier of the connection
+const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+
+/// unique id of the next connection, prefixed by the
+/// time this process started to be unique across restarts
+fn unique_id() -> String {
+    static STARTED: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let started = *STARTED.get_or_init(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    });
+
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{started:016x}{n:016x}")
+}
+
+/// both addresses in the same family, as the header
+/// can't carry an IPv4 and an IPv6 address at once
+fn same_family(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let canonical = |addr: SocketAddr| SocketAddr::new(addr.ip().to_canonical(), addr.port());
+    let v6 = |addr: SocketAddr| match addr {
+        SocketAddr::V4(v4) => SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()),
+        v6 => v6,
+    };
+
+    match (canonical(source), canonical(destination)) {
+        (source, destination) if source.is_ipv4() == destination.is_ipv4() => (source, destination),
+        (source, destination) => (v6(source), v6(destination)),
+    }
+}
+
 pub struct ProxyProtocol {
     client_addr: SocketAddr,
     dest_addr: SocketAddr,
+    version: ProxyVersion,
+
+    /// hostname sent as PP2_TYPE_AUTHORITY along with
+    /// a unique id, only supported by v2 headers
+    authority: Option<String>,
 }
 
 impl ProxyProtocol {
-    pub fn new(client_addr: SocketAddr) -> Self {
-        let dest_addr = match client_addr {
-            SocketAddr::V4(_) => SocketAddr::new([0; 4].into(), 0),
-            SocketAddr::V6(_) => SocketAddr::new([0; 16].into(), 0),
-        };
+    /// header for a client connected from `client_addr`
+    /// to hopper's listener on `dest_addr`
+    pub fn new(client_addr: SocketAddr, dest_addr: SocketAddr, version: ProxyVersion) -> Self {
+        let (client_addr, dest_addr) = same_family(client_addr, dest_addr);
 
         Self {
             client_addr,
             dest_addr,
+            version,
+            authority: None,
+        }
+    }
+
+    /// adds the TLVs carrying `hostname` and a unique connection id
+    pub fn with_tlvs(self, hostname: &str) -> Self {
+        Self {
+            authority: Some(hostname.into()),
+            ..self
+        }
+    }
+
+    fn header(&self) -> BytesMut {
+        // they're either both v4 or both v6
+        let header = match (self.version, self.client_addr, self.dest_addr) {
+            (ProxyVersion::V1, SocketAddr::V4(source), SocketAddr::V4(destination)) => {
+                ProxyHeader::Version1 {
+                    addresses: version1::ProxyAddresses::Ipv4 {
+                        source,
+                        destination,
+                    },
+                }
+            }
+            (ProxyVersion::V1, SocketAddr::V6(source), SocketAddr::V6(destination)) => {
+                ProxyHeader::Version1 {
+                    addresses: version1::ProxyAddresses::Ipv6 {
+                        source,
+                        destination,
+                    },
+                }
+            }
+            (ProxyVersion::V2, SocketAddr::V4(source), SocketAddr::V4(destination)) => {
+                ProxyHeader::Version2 {
+                    command: ProxyCommand::Proxy,
+                    transport_protocol: ProxyTransportProtocol::Stream,
+                    addresses: ProxyAddresses::Ipv4 {
+                        source,
+                        destination,
+                    },
+                }
+            }
+            (ProxyVersion::V2, SocketAddr::V6(source), SocketAddr::V6(destination)) => {
+                ProxyHeader::Version2 {
+                    command: ProxyCommand::Proxy,
+                    transport_protocol: ProxyTransportProtocol::Stream,
+                    addresses: ProxyAddresses::Ipv6 {
+                        source,
+                        destination,
+                    },
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        let mut header = proxy_protocol::encode(header).unwrap();
+
+        if let (ProxyVersion::V2, Some(authority)) = (self.version, &self.authority) {
+            let id = unique_id();
+            let tlvs = [
+                (PP2_TYPE_AUTHORITY, authority.as_bytes()),
+                (PP2_TYPE_UNIQUE_ID, id.as_bytes()),
+            ];
+
+            for (kind, value) in tlvs {
+                header.put_u8(kind);
+                header.put_u16(value.len() as u16);
+                header.put_slice(value);
+            }
+
+            // the length following the 16 bytes fixed part includes the TLVs
+            let length = (header.len() - 16) as u16;
+            header[14..16].copy_from_slice(&length.to_be_bytes());
         }
+
+        header
     }
 }
 
@@ -183,36 +500,13 @@ impl ProxyProtocol {
 impl ConnectionPrimer for ProxyProtocol {
     async fn prime_connection(
         self,
-        stream: &mut Connection,
+        stream: &mut Connection<Stream>,
         og_handshake: DecodedPacket<Handshake>,
     ) -> Result<(), HopperError> {
-        // just send along without doing anything
-
-        // they're either both v4 or both v6
-        let proxy_addr = match (self.client_addr, self.dest_addr) {
-            (SocketAddr::V4(source), SocketAddr::V4(destination)) => ProxyAddresses::Ipv4 {
-                source,
-                destination,
-            },
-            (SocketAddr::V6(source), SocketAddr::V6(destination)) => ProxyAddresses::Ipv6 {
-                source,
-                destination,
-            },
-            _ => unreachable!(),
-        };
-
-        let header = proxy_protocol::encode(ProxyHeader::Version2 {
-            command: ProxyCommand::Proxy,
-            transport_protocol: ProxyTransportProtocol::Stream,
-            addresses: proxy_addr,
-        })
-        .unwrap();
-
         // write proxy header
-        stream.write_buffer().put_slice(&header);
+        stream.write_buffer().put_slice(&self.header());
 
         // send along handshake as-is
-        // og_handshake.as_ref().write_into(stream).await?;
         stream.feed_raw_packet(og_handshake).await?;
 
         Ok(())
@@ -227,7 +521,7 @@ pub(super) struct Passthrough;
 impl ConnectionPrimer for Passthrough {
     async fn prime_connection(
         self,
-        stream: &mut Connection,
+        stream: &mut Connection<Stream>,
         og_handshake: DecodedPacket<Handshake>,
     ) -> Result<(), HopperError> {
         // just send along without doing anything
@@ -235,3 +529,203 @@ impl ConnectionPrimer for Passthrough {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use bytes::{Buf, BufMut, BytesMut};
+    use netherite::{
+        encoding::{str::Str, varint::VarInt},
+        packet::RawPacket,
+        Deserialize, Serialize,
+    };
+    use ring::hmac;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{same_family, BungeeCord, ProxyProtocol, ProxyVersion, Velocity, VELOCITY_CHANNEL};
+    use crate::{
+        protocol::{connection::Connection, login::Profile, types::Property},
+        server::stream::Stream,
+    };
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    /// connected pair of minecraft connections
+    async fn pair() -> (Connection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connect = TcpStream::connect(listener.local_addr().unwrap());
+        let (connected, accepted) = tokio::join!(connect, listener.accept());
+
+        (
+            Connection::new(connected.unwrap()),
+            Connection::new(accepted.unwrap().0),
+        )
+    }
+
+    /// backend asking for forwarding data on `channel`,
+    /// returning the packet it got as an answer
+    async fn mock_backend(mut backend: Connection, channel: &str) -> RawPacket {
+        let mut data = BytesMut::new();
+        VarInt(7).serialize(&mut data);
+        channel.serialize(&mut data);
+        data.put_u8(4);
+
+        let request = RawPacket {
+            packet_id: 0x04,
+            data: data.freeze(),
+        };
+
+        backend.feed_raw(&request).await.unwrap();
+        backend.flush().await.unwrap();
+        backend.read_packet().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn velocity() {
+        let (server, backend) = pair().await;
+        let (client, _) = pair().await;
+        let mut server = Connection::new(Stream::from(server.into_socket()));
+        let mut client = Connection::new(Stream::from(client.into_socket()));
+
+        let backend = tokio::spawn(mock_backend(backend, VELOCITY_CHANNEL));
+        let primer = Velocity::new(addr("1.2.3.4:5000"), "Notch", b"secret"[..].into());
+        primer.answer(&mut server, &mut client).await.unwrap();
+        server.flush().await.unwrap();
+
+        let response = backend.await.unwrap();
+        assert_eq!(response.packet_id, 0x02);
+
+        let mut data = response.data;
+        assert_eq!(VarInt::deserialize(&mut data).unwrap().0, 7);
+        assert_eq!(data.get_u8(), 1);
+
+        // signature followed by the signed payload
+        let (signature, mut payload) = (data.split_to(32), data);
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        hmac::verify(&key, &payload, &signature).unwrap();
+
+        assert_eq!(VarInt::deserialize(&mut payload).unwrap().0, 1);
+        assert_eq!(&*Str::deserialize(&mut payload).unwrap(), "1.2.3.4");
+        payload.advance(16);
+        assert_eq!(&*Str::deserialize(&mut payload).unwrap(), "Notch");
+    }
+
+    #[tokio::test]
+    async fn velocity_unexpected() {
+        let (server, backend) = pair().await;
+        let (client, mut player) = pair().await;
+        let mut server = Connection::new(Stream::from(server.into_socket()));
+        let mut client = Connection::new(Stream::from(client.into_socket()));
+
+        tokio::spawn(mock_backend(backend, "other:channel"));
+        let primer = Velocity::new(addr("1.2.3.4:5000"), "Notch", b"secret"[..].into());
+        primer.answer(&mut server, &mut client).await.unwrap();
+        client.flush().await.unwrap();
+
+        // the request is relayed to the player
+        let relayed = player.read_packet().await.unwrap();
+        assert_eq!(relayed.packet_id, 0x04);
+    }
+
+    #[test]
+    fn velocity_profile() {
+        let profile: Profile = serde_json::from_str(
+            r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch","properties":[{"name":"textures","value":"skin"}]}"#,
+        )
+        .unwrap();
+
+        let primer = Velocity::new(addr("1.2.3.4:5000"), "notch", b"secret"[..].into())
+            .with_profile(Some(&profile));
+
+        let mut payload = primer.data().split_off(32);
+        assert_eq!(VarInt::deserialize(&mut payload).unwrap().0, 1);
+        Str::deserialize(&mut payload).unwrap();
+        assert_eq!(payload.get_u128(), 0x069a79f444e94726a5befca90e38aaf5);
+        assert_eq!(&*Str::deserialize(&mut payload).unwrap(), "Notch");
+
+        assert_eq!(VarInt::deserialize(&mut payload).unwrap().0, 1);
+        assert_eq!(&*Str::deserialize(&mut payload).unwrap(), "textures");
+        assert_eq!(&*Str::deserialize(&mut payload).unwrap(), "skin");
+        assert_eq!(payload.get_u8(), 0);
+    }
+
+    #[test]
+    fn bungeeguard() {
+        let primer = BungeeCord::from_username(addr("1.2.3.4:5000"), "Notch");
+        assert_eq!(primer.properties(), None);
+
+        let primer = primer.with_token(Some("s3cr3t".into()));
+        assert_eq!(
+            primer.properties().unwrap(),
+            r#"[{"name":"bungeeguard-token","value":"s3cr3t"}]"#
+        );
+    }
+
+    #[test]
+    fn bungeecord_properties() {
+        let mut skin = Property::new("textures", "skin");
+        skin.signature = Some("signature".into());
+
+        let primer = BungeeCord::from_username(addr("1.2.3.4:5000"), "Notch")
+            .with_properties(&[skin])
+            .with_token(Some("s3cr3t".into()));
+
+        assert_eq!(
+            primer.properties().unwrap(),
+            r#"[{"name":"textures","value":"skin","signature":"signature"},{"name":"bungeeguard-token","value":"s3cr3t"}]"#
+        );
+    }
+
+    #[test]
+    fn v1_header() {
+        let primer = ProxyProtocol::new(
+            addr("1.2.3.4:5000"),
+            addr("[::ffff:10.0.0.1]:25565"),
+            ProxyVersion::V1,
+        );
+
+        assert_eq!(
+            &primer.header()[..],
+            b"PROXY TCP4 1.2.3.4 10.0.0.1 5000 25565\r\n"
+        );
+    }
+
+    #[test]
+    fn v2_tlvs() {
+        let primer = ProxyProtocol::new(
+            addr("1.2.3.4:5000"),
+            addr("10.0.0.1:25565"),
+            ProxyVersion::V2,
+        )
+        .with_tlvs("mc.example.com");
+
+        let mut header = primer.header().freeze();
+        let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+        assert_eq!(header.len(), 16 + length);
+
+        // addresses are parsed while TLVs are skipped
+        match proxy_protocol::parse(&mut header).unwrap() {
+            proxy_protocol::ProxyHeader::Version2 {
+                addresses: proxy_protocol::version2::ProxyAddresses::Ipv4 { destination, .. },
+                ..
+            } => assert_eq!(SocketAddr::V4(destination), addr("10.0.0.1:25565")),
+            header => panic!("unexpected header {header:?}"),
+        }
+
+        // fixed part, 12 bytes of IPv4 addresses, then the authority
+        let tlvs = &primer.header()[28..];
+        assert_eq!(tlvs[0], 0x02);
+        assert_eq!(&tlvs[3..17], b"mc.example.com");
+        assert_eq!(tlvs[17], 0x05);
+    }
+
+    #[test]
+    fn mixed_families() {
+        let (source, destination) = same_family(addr("[2001:db8::1]:1"), addr("10.0.0.1:2"));
+        assert_eq!(destination, addr("[::ffff:10.0.0.1]:2"));
+        assert!(source.is_ipv6());
+    }
+}
@@ -1,27 +1,33 @@
 use bytes::BufMut;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
-    },
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     select,
 };
 
 #[cfg(feature = "zerocopy")]
 mod linux;
 
-use crate::protocol::connection::{Connection, ConnectionError};
+use crate::{
+    protocol::connection::{Connection, ConnectionError},
+    server::stream::{ReadHalf, Stream, WriteHalf},
+};
 
-async fn flush(into: &mut Connection, from: &mut Connection) -> Result<(), ConnectionError> {
+async fn flush<I, F>(
+    into: &mut Connection<I>,
+    from: &mut Connection<F>,
+) -> Result<(), ConnectionError>
+where
+    I: AsyncRead + AsyncWrite + Unpin,
+    F: AsyncRead + AsyncWrite + Unpin,
+{
     into.write_buffer().put(from.read_buffer());
     into.flush().await
 }
 
 pub async fn flush_bidirectional(
-    mut client: Connection,
-    mut server: Connection,
-) -> Result<(TcpStream, TcpStream), ConnectionError> {
+    mut client: Connection<Stream>,
+    mut server: Connection<Stream>,
+) -> Result<(Stream, Stream), ConnectionError> {
     flush(&mut client, &mut server).await?;
     flush(&mut server, &mut client).await?;
 
@@ -30,8 +36,7 @@ pub async fn flush_bidirectional(
 
 /// Uses an external transferred counter so in an event of an error or
 /// when the future gets dropped by the select data still gets recorded
-#[cfg(not(feature = "zerocopy"))]
-async fn pipe(mut input: OwnedReadHalf, mut output: OwnedWriteHalf, transferred: &mut u64) {
+async fn copy(mut input: ReadHalf, mut output: WriteHalf, transferred: &mut u64) {
     // Accomodate the average MTU of tcp connections
     let mut buffer = [0u8; 2048];
 
@@ -45,23 +50,35 @@ async fn pipe(mut input: OwnedReadHalf, mut output: OwnedWriteHalf, transferred:
 
         *transferred += size as u64; // always safe doing
 
-        if output.write_all(&buffer[..size]).await.is_err() {
+        // encrypted halves keep what the socket can't take until flushed
+        if output.write_all(&buffer[..size]).await.is_err() || output.flush().await.is_err() {
             break;
         }
     }
 }
 
+#[cfg(not(feature = "zerocopy"))]
+async fn pipe(input: ReadHalf, output: WriteHalf, transferred: &mut u64) {
+    copy(input, output, transferred).await
+}
+
 #[cfg(all(feature = "zerocopy", not(target_family = "unix")))]
 compile_error!("feature zerocopy is only supported on unix systems");
 
 #[cfg(feature = "zerocopy")]
 async fn pipe(
-    mut input: OwnedReadHalf,
-    mut output: OwnedWriteHalf,
+    mut input: ReadHalf,
+    mut output: WriteHalf,
     transferred: &mut u64,
 ) -> std::io::Result<()> {
     use std::{io::ErrorKind, os::fd::AsFd};
 
+    // encrypted data has to go through userspace to be deciphered
+    if input.is_encrypted() || output.is_encrypted() {
+        copy(input, output, transferred).await;
+        return Ok(());
+    }
+
     let mut pipe = linux::Pipe::new().unwrap();
     let mut exitflag = false;
 
@@ -81,12 +98,12 @@ async fn pipe(
     loop {
         let _ = input.read(&mut []).await?;
         loop {
-            splice_all!(exitflag, pipe.splice_into(input.as_ref().as_fd()));
+            splice_all!(exitflag, pipe.splice_into(input.as_fd()));
         }
 
         let _ = output.write(&[]).await?;
         loop {
-            let bytes = splice_all!(exitflag, pipe.splice_out(output.as_ref().as_fd()));
+            let bytes = splice_all!(exitflag, pipe.splice_out(output.as_fd()));
             *transferred += bytes as u64;
         }
 
@@ -98,14 +115,10 @@ async fn pipe(
 
 /// returns the number of bytes transferred
 /// `(serverbound, clientbound)`
-pub async fn copy_bidirectional(server: TcpStream, client: TcpStream) -> (u64, u64) {
+pub async fn copy_bidirectional(server: Stream, client: Stream) -> (u64, u64) {
     let mut serverbound = 0;
     let mut clientbound = 0;
 
-    // nagle algo causes ping delay
-    server.set_nodelay(true).unwrap();
-    client.set_nodelay(true).unwrap();
-
     let (rs, ws) = server.into_split();
     let (rc, wc) = client.into_split();
 
@@ -0,0 +1,48 @@
+//! Profile properties forwarded to the backends
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::protocol::types::{PlayerUuid, Property};
+
+/// Properties of players keyed by UUID or username, as
+/// read from a JSON object mapping either to a list of properties
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct ProfileStore(HashMap<String, Vec<Property>>);
+
+impl ProfileStore {
+    /// properties of a player, looked up by UUID then by username
+    pub fn get(&self, uuid: &PlayerUuid, username: &str) -> &[Property] {
+        self.0
+            .get(&uuid.to_string())
+            .or_else(|| self.0.get(username))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProfileStore;
+    use crate::protocol::types::PlayerUuid;
+
+    #[test]
+    fn lookup() {
+        let uuid = PlayerUuid::offline_player("Notch");
+        let store: ProfileStore = serde_json::from_str(&format!(
+            r#"{{
+                "{uuid}": [{{ "name": "textures", "value": "by-uuid", "signature": "sig" }}],
+                "jeb_": [{{ "name": "textures", "value": "by-name" }}]
+            }}"#
+        ))
+        .unwrap();
+
+        assert_eq!(store.get(&uuid, "Notch")[0].value, "by-uuid");
+        assert_eq!(store.get(&uuid, "jeb_")[0].value, "by-uuid");
+
+        let other = PlayerUuid::offline_player("jeb_");
+        assert_eq!(store.get(&other, "jeb_")[0].signature, None);
+        assert!(store.get(&other, "Dinnerbone").is_empty());
+    }
+}
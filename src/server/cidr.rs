@@ -0,0 +1,95 @@
+//! IP ranges in CIDR notation
+
+use std::{fmt::Display, net::IpAddr};
+
+use serde::Deserialize;
+
+/// Range of addresses such as `10.0.0.0/8`. A single
+/// address can be written without prefix length
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.as_str(), None),
+        };
+
+        let address: IpAddr = address
+            .parse()
+            .map_err(|err| format!("invalid address in {value}: {err}"))?;
+
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in {value}"))?,
+            None => max,
+        };
+
+        Ok(Self { address, prefix })
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+impl Cidr {
+    /// whether `address` is in this range. IPv4-mapped IPv6
+    /// addresses, as seen by dual-stack sockets, match IPv4 ranges
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let bits = |address: IpAddr| match address {
+            IpAddr::V4(address) => (u32::from(address) as u128) << 96,
+            IpAddr::V6(address) => u128::from(address),
+        };
+
+        if self.address.is_ipv4() != address.to_canonical().is_ipv4() {
+            return false;
+        }
+
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        bits(self.address) & mask == bits(address.to_canonical()) & mask
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cidr;
+
+    fn cidr(value: &str) -> Cidr {
+        Cidr::try_from(value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn contains() {
+        let private = cidr("10.0.0.0/8");
+        assert!(private.contains("10.20.30.40".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.1.1".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+        assert!(!private.contains("::a00:1".parse().unwrap()));
+
+        assert!(cidr("192.168.1.5").contains("192.168.1.5".parse().unwrap()));
+        assert!(!cidr("192.168.1.5").contains("192.168.1.6".parse().unwrap()));
+        assert!(cidr("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+        assert!(cidr("2001:db8::/32").contains("2001:db8::1".parse().unwrap()));
+
+        assert!(Cidr::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(Cidr::try_from("nope/8".to_string()).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use bytes::BytesMut;
 use netherite::encoding::str::Str;
 use std::{
     collections::hash_map::DefaultHasher,
@@ -11,13 +12,19 @@ use tokio::net::TcpStream;
 
 use crate::{
     protocol::{
-        connection::Connection,
+        connection::{Connection, ConnectionError},
+        login::Profile,
         packet::{DecodedPacket, LazyPacket},
-        packet_impls::{Disconnect, Handshake, JsonChat, LoginStart, State},
+        packet_impls::{
+            Disconnect, Handshake, JsonChat, LoginStart, Ping, State, StatusJson, StatusRequest,
+            StatusResponse,
+        },
     },
     HopperError,
 };
 
+use super::{ingress, stream::Stream};
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 /// verified hostname destination
 pub struct Hostname(Str);
@@ -25,7 +32,7 @@ pub struct Hostname(Str);
 impl Hostname {
     fn from_str(s: &Str) -> Option<Self> {
         let substr = s
-            .split(|c| c == '\x00' || c == '/')
+            .split(['\x00', '/'])
             .next()
             .filter(|&str| !str.is_empty())?;
 
@@ -46,15 +53,29 @@ impl Deref for Hostname {
 
 pub enum NextState {
     Login(LazyPacket<LoginStart>),
+    /// login of a client sent by another server with a Transfer packet
+    Transfer(LazyPacket<LoginStart>),
     Status,
 }
 
+impl NextState {
+    /// login start packet of both logins and transfers
+    pub fn login(&mut self) -> Option<&mut LazyPacket<LoginStart>> {
+        match self {
+            NextState::Login(login) | NextState::Transfer(login) => Some(login),
+            NextState::Status => None,
+        }
+    }
+}
+
 // TODO: reorder fields
 
 pub struct IncomingClient {
     /// user source address
     pub address: SocketAddr,
-    pub connection: Connection,
+    /// address of the listener the user connected to
+    pub destination: SocketAddr,
+    pub connection: Connection<Stream>,
 
     pub handshake: DecodedPacket<Handshake>,
     pub next_state: NextState,
@@ -62,6 +83,9 @@ pub struct IncomingClient {
     /// Sanitized hostname, differs from handshake.server_address as that
     /// may still contain extra information.
     pub hostname: Hostname,
+
+    /// identity verified by the session server, on online-mode routes
+    pub profile: Option<Profile>,
 }
 
 impl IncomingClient {
@@ -83,10 +107,55 @@ impl IncomingClient {
         self.disconnect(err.to_string()).await;
     }
 
+    async fn respond_status_inner(&mut self, status: &StatusJson) -> Result<(), HopperError> {
+        let _: DecodedPacket<StatusRequest> = self.connection.read_packet().await?.try_into()?;
+
+        self.connection
+            .feed_packet(StatusResponse::from_json(status))
+            .await?;
+        self.connection.flush().await?;
+
+        // clients may close the connection without pinging
+        let ping: DecodedPacket<Ping> = match self.connection.read_packet().await {
+            Ok(packet) => packet.try_into()?,
+            Err(ConnectionError::Eof) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        self.connection.feed_packet(ping.into_data()).await?;
+        self.connection.flush().await?;
+
+        Ok(())
+    }
+
+    /// answers a status request on behalf of a server, replying
+    /// to the following ping. Login connections are left untouched
+    pub async fn respond_status(mut self, status: &StatusJson) -> Result<(), HopperError> {
+        if !matches!(self.next_state, NextState::Status) {
+            return Ok(());
+        }
+
+        tokio::time::timeout(Duration::from_secs(2), self.respond_status_inner(status))
+            .await
+            .map_err(|_| HopperError::TimeOut)?
+    }
+
     async fn handshake_inner(
-        (stream, address): (TcpStream, SocketAddr),
+        (mut stream, mut address): (TcpStream, SocketAddr),
+        proxy_protocol: bool,
     ) -> Result<Self, HopperError> {
-        let mut connection = Connection::new(stream);
+        let destination = stream.local_addr().map_err(HopperError::Disconnected)?;
+
+        let mut connection = match proxy_protocol {
+            true => {
+                let mut buf = BytesMut::new();
+                address = ingress::read_header(&mut stream, &mut buf, address).await?;
+
+                Connection::with_buffer(stream.into(), buf)
+            }
+            false => Connection::new(stream.into()),
+        };
+
         let handshake: DecodedPacket<Handshake> = connection.read_packet().await?.try_into()?;
 
         let hostname = Hostname::from_str(&handshake.server_address).ok_or(HopperError::Invalid)?;
@@ -96,19 +165,29 @@ impl IncomingClient {
         let next_state = match handshake.next_state {
             State::Status => NextState::Status,
             State::Login => NextState::Login(connection.read_packet().await?.try_into()?),
+            State::Transfer => NextState::Transfer(connection.read_packet().await?.try_into()?),
         };
 
         Ok(IncomingClient {
             address,
+            destination,
             connection,
             hostname,
             handshake,
             next_state,
+            profile: None,
         })
     }
 
-    pub async fn init(connection: (TcpStream, SocketAddr)) -> Result<Self, HopperError> {
-        tokio::time::timeout(Duration::from_secs(2), Self::handshake_inner(connection))
+    /// reads the handshake of a new connection. When `proxy_protocol` is set
+    /// the client address is taken from the PROXY header preceding it
+    pub async fn init(
+        connection: (TcpStream, SocketAddr),
+        proxy_protocol: bool,
+    ) -> Result<Self, HopperError> {
+        let handshake = Self::handshake_inner(connection, proxy_protocol);
+
+        tokio::time::timeout(Duration::from_secs(2), handshake)
             .await
             .map_err(|_| HopperError::TimeOut)?
     }
@@ -131,17 +210,75 @@ impl std::fmt::Display for IncomingClient {
 }
 
 #[cfg(test)]
-mod test {
-    use netherite::encoding::str::Str;
+pub mod test {
+    use bytes::{BufMut, BytesMut};
+    use netherite::{
+        encoding::{str::Str, varint::VarInt},
+        packet::RawPacket,
+        Serialize,
+    };
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{Hostname, IncomingClient};
+    use crate::protocol::connection::Connection;
+
+    /// connects a player logging in to `hostname` with `protocol` to a
+    /// hopper listener, returning both ends of the connection
+    pub async fn login(
+        protocol: i32,
+        hostname: &str,
+        username: &str,
+        uuid: u128,
+    ) -> (Connection, IncomingClient) {
+        login_with(protocol, hostname, username, &uuid.to_be_bytes()).await
+    }
+
+    /// same as [`login`], `rest` following the username in the login start
+    pub async fn login_with(
+        protocol: i32,
+        hostname: &str,
+        username: &str,
+        rest: &[u8],
+    ) -> (Connection, IncomingClient) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut player = Connection::new(
+            TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap(),
+        );
+
+        let mut handshake = BytesMut::new();
+        VarInt(protocol).serialize(&mut handshake);
+        hostname.serialize(&mut handshake);
+        handshake.put_u16(25565);
+        VarInt(2).serialize(&mut handshake);
 
-    use super::Hostname;
+        let mut login_start = BytesMut::new();
+        username.serialize(&mut login_start);
+        login_start.put_slice(rest);
+
+        for data in [handshake, login_start] {
+            let packet = RawPacket {
+                packet_id: 0x00,
+                data: data.freeze(),
+            };
+            player.feed_raw(&packet).await.unwrap();
+        }
+        player.flush().await.unwrap();
+
+        let client = IncomingClient::init(listener.accept().await.unwrap(), false)
+            .await
+            .unwrap();
+
+        (player, client)
+    }
 
     #[test]
     fn test_hostname() {
         let hostname = Str::from_static("hello\x00extra");
 
         let res = Hostname::from_str(&hostname).unwrap();
-        assert_eq!(&res.0, "hello")
+        assert_eq!(&*res, "hello")
     }
 
     #[test]
@@ -0,0 +1,111 @@
+//! PROXY protocol headers sent by load balancers in front of hopper
+
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use proxy_protocol::{version1, version2, ProxyHeader};
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use crate::HopperError;
+
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// v1 headers are at most 107 bytes long, including CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+/// length of the header contained in `buf`, or None if
+/// more data is needed to know it
+fn header_length(buf: &[u8]) -> Result<Option<usize>, HopperError> {
+    if buf.starts_with(b"PROXY ") {
+        return match buf.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => Ok(Some(end + 2)),
+            None if buf.len() < V1_MAX_LENGTH => Ok(None),
+            None => Err(HopperError::Invalid),
+        };
+    }
+
+    let prefix = buf.len().min(V2_SIGNATURE.len());
+    if buf[..prefix] != V2_SIGNATURE[..prefix] && !b"PROXY ".starts_with(buf) {
+        return Err(HopperError::Invalid);
+    }
+
+    match buf.get(14..16) {
+        Some(&[high, low]) if prefix == V2_SIGNATURE.len() => {
+            Ok(Some(16 + u16::from_be_bytes([high, low]) as usize))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// reads a PROXY protocol header from `stream`, returning the source
+/// address it carries. Data read past the header is left in `buf`
+pub async fn read_header(
+    stream: &mut TcpStream,
+    buf: &mut BytesMut,
+    peer: SocketAddr,
+) -> Result<SocketAddr, HopperError> {
+    let length = loop {
+        if let Some(length) = header_length(buf)? {
+            if buf.len() >= length {
+                break length;
+            }
+        }
+
+        if stream
+            .read_buf(buf)
+            .await
+            .map_err(HopperError::Disconnected)?
+            == 0
+        {
+            return Err(HopperError::Invalid);
+        }
+    };
+
+    let mut header = buf.split_to(length).freeze();
+    let header = proxy_protocol::parse(&mut header).map_err(|_| HopperError::Invalid)?;
+
+    let source = match header {
+        ProxyHeader::Version1 {
+            addresses: version1::ProxyAddresses::Ipv4 { source, .. },
+        } => source.into(),
+        ProxyHeader::Version1 {
+            addresses: version1::ProxyAddresses::Ipv6 { source, .. },
+        } => source.into(),
+        ProxyHeader::Version2 {
+            command: version2::ProxyCommand::Proxy,
+            addresses: version2::ProxyAddresses::Ipv4 { source, .. },
+            ..
+        } => source.into(),
+        ProxyHeader::Version2 {
+            command: version2::ProxyCommand::Proxy,
+            addresses: version2::ProxyAddresses::Ipv6 { source, .. },
+            ..
+        } => source.into(),
+        // LOCAL commands (e.g. health checks) and unknown
+        // addresses refer to the load balancer itself
+        _ => peer,
+    };
+
+    Ok(source)
+}
+
+#[cfg(test)]
+mod test {
+    use super::header_length;
+
+    #[test]
+    fn length() {
+        let v1 = b"PROXY TCP4 1.1.1.1 2.2.2.2 1000 25565\r\nrest";
+        assert_eq!(header_length(v1).unwrap(), Some(39));
+        assert_eq!(header_length(b"PROX").unwrap(), None);
+
+        let mut v2 = super::V2_SIGNATURE.to_vec();
+        assert_eq!(header_length(&v2).unwrap(), None);
+        v2.extend([0x21, 0x11, 0x00, 0x0C]);
+        assert_eq!(header_length(&v2).unwrap(), Some(28));
+
+        assert!(header_length(b"\x10\x00\xfb\x05").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Listening sockets, each with its own routing
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::protocol::packet_impls::StatusJson;
+
+use super::{
+    bridge::forwarding::ForwardStrategy,
+    cidr::Cidr,
+    router::{Route, RouterError},
+    socket::SocketOptions,
+    IncomingClient, Router,
+};
+
+/// Routing and settings of a single listener
+pub struct Listener {
+    router: Arc<dyn Router>,
+
+    /// forwarding strategy of the routes which don't specify one
+    forwarding: Option<ForwardStrategy>,
+
+    /// expect a PROXY protocol header before the handshake
+    proxy_protocol: bool,
+
+    /// ranges PROXY protocol headers are accepted from, any when empty
+    trusted_proxies: Vec<Cidr>,
+
+    /// options of the accepted sockets, and defaults for the backend ones
+    socket: SocketOptions,
+}
+
+impl Listener {
+    pub fn new(
+        router: Arc<dyn Router>,
+        forwarding: Option<ForwardStrategy>,
+        proxy_protocol: bool,
+        trusted_proxies: Vec<Cidr>,
+        socket: SocketOptions,
+    ) -> Self {
+        Self {
+            router,
+            forwarding,
+            proxy_protocol,
+            trusted_proxies,
+            socket,
+        }
+    }
+
+    pub fn proxy_protocol(&self) -> bool {
+        self.proxy_protocol
+    }
+
+    /// whether `peer` can send a PROXY protocol header, nobody
+    /// being trusted when no proxy is
+    pub fn trusts(&self, peer: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(peer))
+    }
+
+    pub fn socket(&self) -> &SocketOptions {
+        &self.socket
+    }
+}
+
+impl Router for Listener {
+    fn route(&self, client: &mut IncomingClient) -> Result<Route, RouterError> {
+        self.router.route(client).map(|route| match route {
+            Route::Backend(destination) => Route::Backend(
+                destination
+                    .or_strategy(self.forwarding)
+                    .or_socket(self.socket),
+            ),
+            redirect => redirect,
+        })
+    }
+
+    fn unknown_status(&self, protocol: i32) -> Option<StatusJson> {
+        self.router.unknown_status(protocol)
+    }
+}
+
+/// binds a listening socket on `address`. IPv6 sockets can be made
+/// IPv6 only, so that an IPv4 socket can be bound on the same port.
+/// With `reuse_port` more sockets can be bound on the same address,
+/// the kernel balancing incoming connections between them
+pub fn bind(
+    address: SocketAddr,
+    v6_only: bool,
+    reuse_port: bool,
+    backlog: i32,
+) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+
+    if address.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+
+    // same as tokio's TcpListener::bind
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(reuse_port)?;
+
+    #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+    if reuse_port {
+        return Err(std::io::ErrorKind::Unsupported.into());
+    }
+
+    socket.bind(&address.into())?;
+    socket.listen(backlog)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
@@ -0,0 +1,218 @@
+//! Routes sending players to another server
+
+use std::{fmt::Display, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{
+    protocol::{
+        connection::ConnectionError,
+        packet::{DecodedPacket, ProtoError},
+        packet_impls::{LoginAcknowledged, LoginSuccess, StatusJson, Transfer, PROTOCOL_1_20_5},
+        types::PlayerUuid,
+    },
+    HopperError,
+};
+
+use super::IncomingClient;
+
+const DEFAULT_PORT: u16 = 25565;
+
+/// Address players are sent to, `host:port` or just `host`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Redirect {
+    host: String,
+    port: u16,
+}
+
+impl TryFrom<String> for Redirect {
+    type Error = String;
+
+    fn try_from(address: String) -> Result<Self, Self::Error> {
+        let (host, port) = match address.rsplit_once(':') {
+            // IPv6 addresses without a port contain colons too
+            Some((host, port)) if !port.contains(']') && !host.ends_with(':') => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("invalid port in redirect {address}"))?;
+                (host, port)
+            }
+            _ => (address.as_str(), DEFAULT_PORT),
+        };
+
+        // IPv6 literals are sent without their brackets
+        let host = match host.strip_prefix('[') {
+            Some(host) => host.strip_suffix(']').unwrap_or_default(),
+            None => host,
+        };
+
+        match host {
+            "" => Err(format!("invalid redirect {address}")),
+            host => Ok(Self {
+                host: host.into(),
+                port,
+            }),
+        }
+    }
+}
+
+impl Display for Redirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.host.contains(':'), self.port) {
+            (false, DEFAULT_PORT) => write!(f, "{}", self.host),
+            (false, port) => write!(f, "{}:{port}", self.host),
+            (true, DEFAULT_PORT) => write!(f, "[{}]", self.host),
+            (true, port) => write!(f, "[{}]:{port}", self.host),
+        }
+    }
+}
+
+impl Redirect {
+    fn message(&self) -> String {
+        format!("This server has moved to {self}")
+    }
+
+    /// sends `client` to this address with a Transfer packet. Clients
+    /// older than 1.20.5 are kicked with the new address instead, and
+    /// status requests are answered with it
+    pub async fn send(&self, mut client: IncomingClient) -> Result<(), HopperError> {
+        let protocol = client.handshake.protocol_version.0;
+
+        let login = match client.next_state.login() {
+            Some(login) if protocol >= PROTOCOL_1_20_5 => login.data()?,
+            Some(_) => {
+                client.disconnect(self.message()).await;
+                return Ok(());
+            }
+            None => {
+                let status = StatusJson::new("Hopper", protocol, &self.message());
+                return client.respond_status(&status).await;
+            }
+        };
+
+        let uuid = login.uuid(protocol).map_err(ProtoError::from)?;
+        let username = login.username.clone();
+        let uuid = uuid.unwrap_or_else(|| PlayerUuid::offline_player(&username));
+
+        let transfer = async {
+            let connection = &mut client.connection;
+            connection
+                .feed_packet(LoginSuccess::new(uuid, &username, protocol))
+                .await?;
+            connection.flush().await?;
+
+            // the client enters the configuration phase,
+            // where it can be told to connect somewhere else
+            let _: DecodedPacket<LoginAcknowledged> = connection.read_packet().await?.try_into()?;
+
+            let transfer = Transfer::new(&self.host, self.port);
+            connection.feed_packet(transfer).await?;
+            connection.flush().await?;
+
+            // closing with unread data would reset the connection, possibly
+            // before the client reads the transfer. Wait for it to leave
+            loop {
+                match connection.read_packet().await {
+                    Ok(_) => continue,
+                    Err(ConnectionError::Eof) => break Ok(()),
+                    Err(err) => break Err(HopperError::from(err)),
+                }
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), transfer)
+            .await
+            .map_err(|_| HopperError::TimeOut)?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Buf;
+    use netherite::{
+        encoding::{str::Str, varint::VarInt},
+        packet::RawPacket,
+        Deserialize,
+    };
+
+    use super::Redirect;
+    use crate::{
+        protocol::{connection::Connection, packet_impls::PROTOCOL_1_20_5},
+        server::{client, IncomingClient},
+    };
+
+    /// connects a client with `protocol` to a hopper listener
+    async fn login(protocol: i32) -> (Connection, IncomingClient) {
+        let uuid = 0x069a79f444e94726a5befca90e38aaf5;
+        client::test::login(protocol, "old.example.com", "Notch", uuid).await
+    }
+
+    fn redirect(address: &str) -> Result<Redirect, String> {
+        Redirect::try_from(address.to_string())
+    }
+
+    #[test]
+    fn parse() {
+        let moved = redirect("new.example.com:25570").unwrap();
+        assert_eq!(
+            (moved.host.as_str(), moved.port),
+            ("new.example.com", 25570)
+        );
+        assert_eq!(moved.to_string(), "new.example.com:25570");
+
+        let moved = redirect("new.example.com").unwrap();
+        assert_eq!(moved.port, 25565);
+        assert_eq!(moved.to_string(), "new.example.com");
+
+        let moved = redirect("[::1]:25570").unwrap();
+        assert_eq!((moved.host.as_str(), moved.port), ("::1", 25570));
+        assert_eq!(moved.to_string(), "[::1]:25570");
+        assert_eq!(redirect("[::1]").unwrap().port, 25565);
+        assert!(redirect("[::1:25570").is_err());
+        assert!(redirect("example.com:port").is_err());
+        assert!(redirect(":25565").is_err());
+    }
+
+    #[tokio::test]
+    async fn transfer() {
+        let (mut player, client) = login(PROTOCOL_1_20_5).await;
+        let moved = redirect("new.example.com:25570").unwrap();
+        let hopper = tokio::spawn(async move { moved.send(client).await });
+
+        let login_success = player.read_packet().await.unwrap();
+        assert_eq!(login_success.packet_id, 0x02);
+        let mut data = login_success.data;
+        assert_eq!(data.get_u128(), 0x069a79f444e94726a5befca90e38aaf5);
+        assert_eq!(&*Str::deserialize(&mut data).unwrap(), "Notch");
+
+        let acknowledged = RawPacket {
+            packet_id: 0x03,
+            data: Default::default(),
+        };
+        player.feed_raw(&acknowledged).await.unwrap();
+        player.flush().await.unwrap();
+
+        let transfer = player.read_packet().await.unwrap();
+        assert_eq!(transfer.packet_id, 0x0B);
+        let mut data = transfer.data;
+        assert_eq!(&*Str::deserialize(&mut data).unwrap(), "new.example.com");
+        assert_eq!(VarInt::deserialize(&mut data).unwrap().0, 25570);
+
+        drop(player);
+        hopper.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn kick() {
+        let (mut player, client) = login(765).await;
+        let moved = redirect("new.example.com").unwrap();
+        moved.send(client).await.unwrap();
+
+        let disconnect = player.read_packet().await.unwrap();
+        assert_eq!(disconnect.packet_id, 0x00);
+        let mut data = disconnect.data;
+        let reason = Str::deserialize(&mut data).unwrap();
+        assert!(reason.contains("This server has moved to new.example.com"));
+    }
+}
@@ -1,35 +1,235 @@
-use std::net::SocketAddr;
+use std::{net::IpAddr, sync::Arc};
 
-use super::{bridge::forwarding::ForwardStrategy, IncomingClient};
+use arc_swap::ArcSwap;
+
+use crate::protocol::packet_impls::StatusJson;
+
+use super::{
+    backend::{proxy::OutboundProxy, BackendAddr},
+    bridge::forwarding::ForwardStrategy,
+    redirect::Redirect,
+    socket::SocketOptions,
+    status::{OfflineStatus, StatusCache},
+    IncomingClient,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum RouterError {
     #[error("no server with such hostname has been found")]
     NoServer,
+
+    #[error("no healthy server is available for this hostname")]
+    Unavailable,
+
+    #[error("no source address of the same family as the server is configured")]
+    NoSource,
 }
 
 // #[async_trait::async_trait]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Destination {
-    address: SocketAddr,
-    strategy: ForwardStrategy,
+    address: BackendAddr,
+    /// None when the route doesn't specify a strategy
+    strategy: Option<ForwardStrategy>,
+    socket: SocketOptions,
+
+    /// local address to connect from
+    source: Option<IpAddr>,
+
+    /// proxy the connection is tunneled through
+    proxy: Option<OutboundProxy>,
+
+    /// send TLVs along PROXY protocol v2 headers
+    tlvs: bool,
+
+    /// BungeeGuard token sent along bungeecord forwarding
+    bungeeguard: Option<Arc<str>>,
+
+    /// forward the UUID offline clients claim with bungeecord forwarding
+    client_uuid: bool,
+
+    /// players are authenticated by hopper before reaching the backend
+    online_mode: bool,
+
+    /// answer to clients when the backend can't be reached
+    offline: Option<OfflineStatus>,
+
+    /// status responses of the backend answered to pings
+    status_cache: Option<StatusCache>,
 }
 
 impl Destination {
-    pub fn new(address: SocketAddr, strategy: ForwardStrategy) -> Self {
-        Self { address, strategy }
+    pub fn new(address: BackendAddr, strategy: Option<ForwardStrategy>) -> Self {
+        Self {
+            address,
+            strategy,
+            socket: SocketOptions::default(),
+            source: None,
+            proxy: None,
+            tlvs: false,
+            bungeeguard: None,
+            client_uuid: false,
+            online_mode: false,
+            offline: None,
+            status_cache: None,
+        }
+    }
+
+    pub fn with_status_cache(self, status_cache: Option<StatusCache>) -> Self {
+        Self {
+            status_cache,
+            ..self
+        }
+    }
+
+    pub fn with_offline(self, offline: Option<OfflineStatus>) -> Self {
+        Self { offline, ..self }
+    }
+
+    pub fn with_online_mode(self, online_mode: bool) -> Self {
+        Self {
+            online_mode,
+            ..self
+        }
     }
 
-    pub fn address(&self) -> SocketAddr {
-        self.address
+    pub fn with_bungeeguard(self, bungeeguard: Option<Arc<str>>) -> Self {
+        Self {
+            bungeeguard,
+            ..self
+        }
+    }
+
+    pub fn with_client_uuid(self, client_uuid: bool) -> Self {
+        Self {
+            client_uuid,
+            ..self
+        }
+    }
+
+    pub fn with_tlvs(self, tlvs: bool) -> Self {
+        Self { tlvs, ..self }
+    }
+
+    pub fn with_proxy(self, proxy: Option<OutboundProxy>) -> Self {
+        Self { proxy, ..self }
+    }
+
+    pub fn with_source(self, source: Option<IpAddr>) -> Self {
+        Self { source, ..self }
+    }
+
+    /// socket options set by the route
+    pub fn with_socket(self, socket: SocketOptions) -> Self {
+        Self { socket, ..self }
+    }
+
+    /// uses the options of `defaults` the route didn't set
+    pub fn or_socket(self, defaults: SocketOptions) -> Self {
+        Self {
+            socket: self.socket.or(defaults),
+            ..self
+        }
+    }
+
+    /// uses `strategy` if the route didn't specify one
+    pub fn or_strategy(self, strategy: Option<ForwardStrategy>) -> Self {
+        Self {
+            strategy: self.strategy.or(strategy),
+            ..self
+        }
+    }
+
+    pub fn address(&self) -> &BackendAddr {
+        &self.address
     }
 
     pub fn strategy(&self) -> ForwardStrategy {
-        self.strategy
+        self.strategy.unwrap_or_default()
+    }
+
+    pub fn tlvs(&self) -> bool {
+        self.tlvs
+    }
+
+    pub fn bungeeguard(&self) -> Option<&Arc<str>> {
+        self.bungeeguard.as_ref()
+    }
+
+    pub fn client_uuid(&self) -> bool {
+        self.client_uuid
+    }
+
+    pub fn online_mode(&self) -> bool {
+        self.online_mode
+    }
+
+    pub fn offline(&self) -> Option<&OfflineStatus> {
+        self.offline.as_ref()
+    }
+
+    pub fn status_cache(&self) -> Option<&StatusCache> {
+        self.status_cache.as_ref()
+    }
+
+    pub fn proxy(&self) -> Option<&OutboundProxy> {
+        self.proxy.as_ref()
+    }
+
+    pub fn source(&self) -> Option<IpAddr> {
+        self.source
+    }
+
+    pub fn socket(&self) -> &SocketOptions {
+        &self.socket
     }
 }
 
+/// Where a client is sent by its route
+#[derive(Debug, Clone)]
+pub enum Route {
+    Backend(Destination),
+    /// another server the client is told to connect to
+    Redirect(Redirect),
+}
+
 pub trait Router: Send + Sync {
-    fn route(&self, client: &mut IncomingClient) -> Result<Destination, RouterError>;
+    fn route(&self, client: &mut IncomingClient) -> Result<Route, RouterError>;
+
+    /// status answered to the pings of clients speaking
+    /// `protocol` that can't be routed
+    fn unknown_status(&self, _protocol: i32) -> Option<StatusJson> {
+        None
+    }
+}
+
+/// Router that can be atomically replaced while
+/// other connections are being routed
+pub struct SwappableRouter<R>(ArcSwap<R>);
+
+impl<R> SwappableRouter<R> {
+    pub fn new(router: R) -> Self {
+        Self(ArcSwap::from_pointee(router))
+    }
+
+    pub fn load(&self) -> Arc<R> {
+        self.0.load_full()
+    }
+
+    /// replaces the current router, returning the previous one.
+    /// Connections already being routed keep using the previous router
+    pub fn swap(&self, router: R) -> Arc<R> {
+        self.0.swap(Arc::new(router))
+    }
+}
+
+impl<R: Router> Router for SwappableRouter<R> {
+    fn route(&self, client: &mut IncomingClient) -> Result<Route, RouterError> {
+        self.0.load().route(client)
+    }
+
+    fn unknown_status(&self, protocol: i32) -> Option<StatusJson> {
+        self.0.load().unknown_status(protocol)
+    }
 }
@@ -0,0 +1,217 @@
+//! TCP options of client and backend sockets
+
+use std::{io, time::Duration};
+
+use serde::Deserialize;
+use socket2::{SockRef, TcpKeepalive};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveOptions {
+    /// seconds of inactivity before the first probe is sent
+    pub idle: u64,
+
+    /// seconds between two probes
+    pub interval: Option<u64>,
+
+    /// unanswered probes before the connection is dropped
+    pub count: Option<u32>,
+}
+
+/// Options applied to a TCP socket. Unset options are left to the system
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    pub keepalive: Option<KeepaliveOptions>,
+
+    /// SO_RCVBUF in bytes
+    #[serde(alias = "recv-buffer")]
+    pub recv_buffer: Option<usize>,
+
+    /// SO_SNDBUF in bytes
+    #[serde(alias = "send-buffer")]
+    pub send_buffer: Option<usize>,
+
+    /// TCP_USER_TIMEOUT in milliseconds, linux only
+    #[serde(alias = "user-timeout")]
+    pub user_timeout: Option<u64>,
+
+    /// whole TOS byte (IPv4) or traffic class (IPv6)
+    pub tos: Option<u8>,
+
+    /// DSCP marking, replacing the upper six bits of `tos`
+    pub dscp: Option<u8>,
+}
+
+impl SocketOptions {
+    /// uses the options of `defaults` which aren't set in `self`
+    pub fn or(self, defaults: SocketOptions) -> Self {
+        // tos and dscp set the same byte, so they're inherited together
+        let (tos, dscp) = match (self.tos, self.dscp) {
+            (None, None) => (defaults.tos, defaults.dscp),
+            marking => marking,
+        };
+
+        Self {
+            keepalive: self.keepalive.or(defaults.keepalive),
+            recv_buffer: self.recv_buffer.or(defaults.recv_buffer),
+            send_buffer: self.send_buffer.or(defaults.send_buffer),
+            user_timeout: self.user_timeout.or(defaults.user_timeout),
+            tos,
+            dscp,
+        }
+    }
+
+    fn tos(&self) -> Option<u32> {
+        let tos = match (self.tos, self.dscp) {
+            (tos, Some(dscp)) => tos.unwrap_or(0) & 0b11 | (dscp & 0b111111) << 2,
+            (tos, None) => tos?,
+        };
+
+        Some(tos.into())
+    }
+
+    /// sets every configured option on `socket`, along with TCP_NODELAY
+    /// as Nagle's algorithm delays small packets such as pings
+    pub fn apply(&self, socket: SockRef<'_>, ipv6: bool) -> io::Result<()> {
+        socket.set_nodelay(true)?;
+
+        if let Some(keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(&keepalive.into_tcp()?)?;
+        }
+
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(timeout) = self.user_timeout {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            socket.set_tcp_user_timeout(Some(Duration::from_millis(timeout)))?;
+
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            return Err(unsupported("user-timeout", timeout));
+        }
+
+        if let Some(tos) = self.tos() {
+            match ipv6 {
+                #[cfg(any(
+                    target_os = "linux",
+                    target_os = "android",
+                    target_os = "macos",
+                    target_os = "freebsd",
+                    target_os = "netbsd",
+                    target_os = "openbsd"
+                ))]
+                true => socket.set_tclass_v6(tos)?,
+                #[cfg(not(any(
+                    target_os = "linux",
+                    target_os = "android",
+                    target_os = "macos",
+                    target_os = "freebsd",
+                    target_os = "netbsd",
+                    target_os = "openbsd"
+                )))]
+                true => return Err(unsupported("tos", tos)),
+                #[cfg(not(any(target_os = "solaris", target_os = "illumos")))]
+                false => socket.set_tos(tos)?,
+                #[cfg(any(target_os = "solaris", target_os = "illumos"))]
+                false => return Err(unsupported("tos", tos)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[allow(dead_code)] // only used on some platforms
+fn unsupported(option: &str, value: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{option} = {value} is not supported on this platform"),
+    )
+}
+
+impl KeepaliveOptions {
+    fn into_tcp(self) -> io::Result<TcpKeepalive> {
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(self.idle));
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            windows
+        ))]
+        let keepalive = match self.interval {
+            Some(interval) => keepalive.with_interval(Duration::from_secs(interval)),
+            None => keepalive,
+        };
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        ))]
+        let keepalive = match self.count {
+            Some(count) => keepalive.with_retries(count),
+            None => keepalive,
+        };
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            windows
+        )))]
+        if let Some(interval) = self.interval {
+            return Err(unsupported("keepalive interval", interval));
+        }
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        )))]
+        if let Some(count) = self.count {
+            return Err(unsupported("keepalive count", count));
+        }
+
+        Ok(keepalive)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SocketOptions;
+
+    #[test]
+    fn merge() {
+        let listener = SocketOptions {
+            recv_buffer: Some(1024),
+            tos: Some(0x10),
+            ..Default::default()
+        };
+
+        let route = SocketOptions {
+            send_buffer: Some(2048),
+            dscp: Some(46),
+            ..Default::default()
+        };
+
+        let merged = route.or(listener);
+        assert_eq!(merged.recv_buffer, Some(1024));
+        assert_eq!(merged.send_buffer, Some(2048));
+        assert_eq!(merged.tos(), Some(46 << 2));
+
+        assert_eq!(listener.or(route).tos(), Some(0x10));
+    }
+}
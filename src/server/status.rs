@@ -0,0 +1,415 @@
+//! Server list entries answered by hopper itself
+
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{protocol::packet_impls::StatusJson, HopperError};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusSource {
+    #[serde(default)]
+    motd: String,
+    version: Option<String>,
+    #[serde(default)]
+    players: u32,
+    #[serde(alias = "max-players", default)]
+    max_players: u32,
+    favicon: Option<PathBuf>,
+}
+
+/// Same fields as a status, along with the message players are kicked with
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OfflineSource {
+    #[serde(default)]
+    motd: String,
+    version: Option<String>,
+    #[serde(default)]
+    players: u32,
+    #[serde(alias = "max-players", default)]
+    max_players: u32,
+    favicon: Option<PathBuf>,
+    kick: Option<String>,
+}
+
+/// Entry shown in the server list. The favicon, a 64x64 PNG
+/// file, is read and encoded when the configuration is loaded
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "StatusSource")]
+pub struct ServerStatus {
+    motd: String,
+    /// shown instead of the ping, as if the client was incompatible
+    version: Option<String>,
+    players: u32,
+    max_players: u32,
+    /// `data:image/png;base64,` URI, shared by every route clone
+    favicon: Option<Arc<str>>,
+}
+
+impl TryFrom<StatusSource> for ServerStatus {
+    type Error = String;
+
+    fn try_from(source: StatusSource) -> Result<Self, Self::Error> {
+        let favicon = match source.favicon {
+            Some(path) => {
+                let png = std::fs::read(&path)
+                    .map_err(|err| format!("cannot read favicon {}: {err}", path.display()))?;
+                let favicon = favicon(&png).map_err(|err| format!("{}: {err}", path.display()))?;
+                Some(favicon.into())
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            motd: source.motd,
+            version: source.version,
+            players: source.players,
+            max_players: source.max_players,
+            favicon,
+        })
+    }
+}
+
+/// encodes a 64x64 PNG image as the favicon of a status response
+fn favicon(png: &[u8]) -> Result<String, &'static str> {
+    if !png.starts_with(PNG_SIGNATURE) {
+        return Err("the favicon is not a PNG image");
+    }
+
+    // the image header chunk comes first, starting with width and height
+    let size = png.get(16..24).ok_or("the favicon is truncated")?;
+    if size != [0, 0, 0, 64, 0, 0, 0, 64] {
+        return Err("the favicon must be 64x64 pixels");
+    }
+
+    Ok(format!("data:image/png;base64,{}", STANDARD.encode(png)))
+}
+
+impl ServerStatus {
+    /// status response to a client speaking `protocol`
+    pub fn json(&self, protocol: i32) -> StatusJson {
+        // clients show the version name instead of the ping
+        // when the server doesn't speak their protocol
+        let (version, protocol) = match self.version {
+            Some(ref version) => (version.as_str(), -1),
+            None => ("Hopper", protocol),
+        };
+
+        StatusJson::detailed(
+            version,
+            protocol,
+            &self.motd,
+            (self.players, self.max_players),
+            self.favicon.as_deref(),
+        )
+    }
+}
+
+/// Status of a route whose backend can't be reached,
+/// shared by every clone of the route
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "OfflineSource")]
+pub struct OfflineStatus(Arc<Offline>);
+
+#[derive(Debug, PartialEq, Eq)]
+struct Offline {
+    status: ServerStatus,
+    /// message players logging in are kicked with, the motd when unset
+    kick: Option<String>,
+}
+
+impl TryFrom<OfflineSource> for OfflineStatus {
+    type Error = String;
+
+    fn try_from(source: OfflineSource) -> Result<Self, Self::Error> {
+        let status = StatusSource {
+            motd: source.motd,
+            version: source.version,
+            players: source.players,
+            max_players: source.max_players,
+            favicon: source.favicon,
+        };
+
+        Ok(Self(Arc::new(Offline {
+            status: status.try_into()?,
+            kick: source.kick,
+        })))
+    }
+}
+
+impl OfflineStatus {
+    pub fn status(&self) -> &ServerStatus {
+        &self.0.status
+    }
+
+    pub fn kick(&self) -> &str {
+        self.0.kick.as_deref().unwrap_or(&self.0.status.motd)
+    }
+}
+
+#[derive(Debug)]
+struct CachedStatus {
+    json: Arc<StatusJson>,
+    /// parsed response of a backend which answered with the protocol
+    /// of the client, so that every client gets its own protocol back
+    echoed: Option<Value>,
+    fetched: Instant,
+}
+
+impl CachedStatus {
+    fn new(json: StatusJson, queried: i32) -> Self {
+        let echoed = serde_json::from_str::<Value>(json.as_str())
+            .ok()
+            .filter(|value| value["version"]["protocol"] == queried);
+
+        Self {
+            json: Arc::new(json),
+            echoed,
+            fetched: Instant::now(),
+        }
+    }
+
+    fn json(&self, protocol: i32) -> Arc<StatusJson> {
+        match self.echoed {
+            Some(ref echoed) => {
+                let mut echoed = echoed.clone();
+                echoed["version"]["protocol"] = protocol.into();
+                Arc::new(echoed.into())
+            }
+            None => self.json.clone(),
+        }
+    }
+}
+
+type Refresh = Shared<BoxFuture<'static, Option<Arc<CachedStatus>>>>;
+
+/// Status response of the backend of a route, kept for a number of seconds
+/// and shared by every clone of the route. A single request reaches the
+/// backend at a time, no matter how many clients are pinging
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "u64")]
+pub struct StatusCache(Arc<Cache>);
+
+#[derive(Debug)]
+struct Cache {
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entry: Option<Arc<CachedStatus>>,
+    /// request in flight, awaited by the pings finding no entry
+    refresh: Option<Refresh>,
+}
+
+impl std::fmt::Debug for CacheState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheState")
+            .field("entry", &self.entry)
+            .field("refreshing", &self.refresh.is_some())
+            .finish()
+    }
+}
+
+impl From<u64> for StatusCache {
+    fn from(seconds: u64) -> Self {
+        Self(Arc::new(Cache {
+            ttl: Duration::from_secs(seconds),
+            state: Default::default(),
+        }))
+    }
+}
+
+// routes are the same as long as the configuration is
+impl PartialEq for StatusCache {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ttl == other.0.ttl
+    }
+}
+
+impl StatusCache {
+    /// status answered to a client speaking `protocol`. Expired statuses keep
+    /// being answered while `query` refreshes them in the background, clients
+    /// only wait for it when there is nothing to answer with. A failed query
+    /// leaves the previous status in place, `None` is returned without one
+    pub async fn get<F>(&self, protocol: i32, query: impl FnOnce() -> F) -> Option<Arc<StatusJson>>
+    where
+        F: Future<Output = Result<StatusJson, HopperError>> + Send + 'static,
+    {
+        let refresh = {
+            let mut state = self.0.state.lock().unwrap();
+
+            let expired = match state.entry {
+                Some(ref entry) => entry.fetched.elapsed() >= self.0.ttl,
+                None => true,
+            };
+
+            if expired && state.refresh.is_none() {
+                state.refresh = Some(self.refresh(protocol, query()));
+            }
+
+            match state.entry {
+                Some(ref entry) => return Some(entry.json(protocol)),
+                None => state.refresh.clone()?,
+            }
+        };
+
+        Some(refresh.await?.json(protocol))
+    }
+
+    /// runs `query` in its own task, so that it completes even
+    /// when every client waiting for it goes away
+    fn refresh<F>(&self, protocol: i32, query: F) -> Refresh
+    where
+        F: Future<Output = Result<StatusJson, HopperError>> + Send + 'static,
+    {
+        let cache = self.0.clone();
+        let task = tokio::spawn(async move {
+            let result = query.await;
+            let mut state = cache.state.lock().unwrap();
+            state.refresh = None;
+
+            match result {
+                Ok(json) => {
+                    let entry = Arc::new(CachedStatus::new(json, protocol));
+                    state.entry = Some(entry.clone());
+                    Some(entry)
+                }
+                Err(err) => {
+                    log::debug!("Cannot refresh a cached status: {err}");
+                    None
+                }
+            }
+        });
+
+        task.map(|result| result.ok().flatten()).boxed().shared()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{protocol::packet_impls::StatusJson, HopperError};
+
+    use super::{favicon, OfflineStatus, ServerStatus, StatusCache, StatusSource, PNG_SIGNATURE};
+
+    fn png(width: u8, height: u8) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(b"\0\0\0\x0dIHDR");
+        png.extend_from_slice(&[0, 0, 0, width, 0, 0, 0, height]);
+        png
+    }
+
+    #[test]
+    fn favicons() {
+        let encoded = favicon(&png(64, 64)).unwrap();
+        assert!(encoded.starts_with("data:image/png;base64,iVBORw0KGgo"));
+
+        assert!(favicon(&png(32, 32)).is_err());
+        assert!(favicon(b"GIF89a").is_err());
+        assert!(favicon(PNG_SIGNATURE).is_err());
+    }
+
+    #[test]
+    fn status_json() {
+        let path = std::env::temp_dir().join(format!("hopper-test-{}.png", std::process::id()));
+        std::fs::write(&path, png(64, 64)).unwrap();
+
+        let status = ServerStatus::try_from(StatusSource {
+            motd: "§cNothing here".into(),
+            version: Some("§cUnknown".into()),
+            players: 1,
+            max_players: 20,
+            favicon: Some(path.clone()),
+        });
+        std::fs::remove_file(path).unwrap();
+        let status = status.unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(status.json(766).as_str()).unwrap();
+        assert_eq!(json["version"]["name"], "§cUnknown");
+        assert_eq!(json["version"]["protocol"], -1);
+        assert_eq!(json["players"]["online"], 1);
+        assert_eq!(json["players"]["max"], 20);
+        assert_eq!(json["description"]["text"], "§cNothing here");
+        assert!(json["favicon"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png"));
+    }
+
+    #[test]
+    fn offline_kick() {
+        let offline: OfflineStatus =
+            serde_json::from_str(r#"{ "motd": "§cOffline", "max-players": 20 }"#).unwrap();
+        assert_eq!(offline.kick(), "§cOffline");
+        assert_eq!(offline.status().max_players, 20);
+
+        let offline: OfflineStatus =
+            serde_json::from_str(r#"{ "motd": "§cOffline", "kick": "Come back later" }"#).unwrap();
+        assert_eq!(offline.kick(), "Come back later");
+
+        // only offline statuses kick players
+        let unknown = serde_json::from_str::<ServerStatus>(r#"{ "motd": "?", "kick": "Bye" }"#);
+        assert!(unknown.is_err());
+    }
+
+    #[tokio::test]
+    async fn status_cache() {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let status = |protocol, result: Result<(), HopperError>| {
+            let queries = queries.clone();
+            move || async move {
+                queries.fetch_add(1, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+                result.map(|_| StatusJson::new("Backend", protocol, "cached"))
+            }
+        };
+
+        let cache = StatusCache::from(60);
+        let failed = cache.get(766, status(766, Err(HopperError::TimeOut)));
+        assert!(failed.await.is_none());
+
+        // pings finding no status all wait for the same request
+        let pings = (0..8).map(|_| cache.get(766, status(766, Ok(()))));
+        let pings = futures::future::join_all(pings).await;
+        assert!(pings.iter().all(Option::is_some));
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+
+        // backends answering with the protocol of the client do so for every client
+        let cached = cache.get(4, status(4, Ok(()))).await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(cached.as_str()).unwrap();
+        assert_eq!(json["version"]["protocol"], 4);
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+
+        // while the others always answer with their own
+        let cache = StatusCache::from(0);
+        cache.get(766, status(765, Ok(()))).await.unwrap();
+        let cached = cache.get(4, status(765, Err(HopperError::TimeOut)));
+        let json: serde_json::Value = serde_json::from_str(cached.await.unwrap().as_str()).unwrap();
+        assert_eq!(json["version"]["protocol"], 765);
+
+        // the expired status is kept when its refresh fails
+        tokio::task::yield_now().await;
+        assert!(cache.get(766, status(765, Ok(()))).await.is_some());
+    }
+}
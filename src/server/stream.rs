@@ -0,0 +1,212 @@
+//! Streams backends can be reached through
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(unix)]
+use tokio::net::{unix, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{tcp, TcpStream},
+};
+
+use crate::protocol::crypto::cfb8::{Decrypting, Encrypting};
+
+/// Either a TCP or a unix stream, or a TCP
+/// stream encrypted by an online-mode login
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Encrypted(
+        Box<Decrypting<tcp::OwnedReadHalf>>,
+        Box<Encrypting<tcp::OwnedWriteHalf>>,
+    ),
+}
+
+pub enum ReadHalf {
+    Tcp(tcp::OwnedReadHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedReadHalf),
+    Encrypted(Box<Decrypting<tcp::OwnedReadHalf>>),
+}
+
+pub enum WriteHalf {
+    Tcp(tcp::OwnedWriteHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedWriteHalf),
+    Encrypted(Box<Encrypting<tcp::OwnedWriteHalf>>),
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Self::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Self::Unix(stream)
+    }
+}
+
+impl Stream {
+    pub fn into_split(self) -> (ReadHalf, WriteHalf) {
+        match self {
+            Stream::Tcp(stream) => {
+                let (read, write) = stream.into_split();
+                (ReadHalf::Tcp(read), WriteHalf::Tcp(write))
+            }
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                let (read, write) = stream.into_split();
+                (ReadHalf::Unix(read), WriteHalf::Unix(write))
+            }
+            Stream::Encrypted(read, write) => {
+                (ReadHalf::Encrypted(read), WriteHalf::Encrypted(write))
+            }
+        }
+    }
+
+    /// the underlying TCP stream, if any
+    pub fn tcp(&self) -> Option<&TcpStream> {
+        match self {
+            Stream::Tcp(stream) => Some(stream),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+            Stream::Encrypted(read, _) => Some(read.get_ref().as_ref()),
+        }
+    }
+
+    /// encrypts the stream with `secret`, decrypting in place
+    /// `read` which has already been read from it. Only
+    /// TCP streams can be encrypted, others are returned as is
+    pub fn encrypt(self, secret: &[u8; 16], read: &mut [u8]) -> Result<Self, Self> {
+        let stream = match self {
+            Stream::Tcp(stream) => stream,
+            stream => return Err(stream),
+        };
+
+        let (reader, writer) = stream.into_split();
+        let mut reader = Decrypting::new(reader, secret);
+        reader.decrypt(read);
+
+        Ok(Stream::Encrypted(
+            Box::new(reader),
+            Box::new(Encrypting::new(writer, secret)),
+        ))
+    }
+}
+
+#[cfg(feature = "zerocopy")]
+impl ReadHalf {
+    /// encrypted halves can't be spliced
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, ReadHalf::Encrypted(_))
+    }
+}
+
+#[cfg(feature = "zerocopy")]
+impl WriteHalf {
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, WriteHalf::Encrypted(_))
+    }
+}
+
+/// calls `$method` on whichever stream `$self` wraps, encrypted
+/// streams delegate to the half matching the direction
+macro_rules! delegate {
+    ($ty:ident, $self:expr, $inner:ident => $method:expr) => {
+        delegate!($ty, $self, $inner => $method, $ty::Encrypted($inner))
+    };
+    ($ty:ident, $self:expr, $inner:ident => $method:expr, $encrypted:pat) => {
+        match $self.get_mut() {
+            $ty::Tcp($inner) => $method,
+            #[cfg(unix)]
+            $ty::Unix($inner) => $method,
+            $encrypted => $method,
+        }
+    };
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        delegate!(Stream, self, inner => Pin::new(inner).poll_read(cx, buf), Stream::Encrypted(inner, _))
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        delegate!(Stream, self, inner => Pin::new(inner).poll_write(cx, buf), Stream::Encrypted(_, inner))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        delegate!(Stream, self, inner => Pin::new(inner).poll_flush(cx), Stream::Encrypted(_, inner))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        delegate!(Stream, self, inner => Pin::new(inner).poll_shutdown(cx), Stream::Encrypted(_, inner))
+    }
+}
+
+impl AsyncRead for ReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        delegate!(ReadHalf, self, inner => Pin::new(inner).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for WriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        delegate!(WriteHalf, self, inner => Pin::new(inner).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        delegate!(WriteHalf, self, inner => Pin::new(inner).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        delegate!(WriteHalf, self, inner => Pin::new(inner).poll_shutdown(cx))
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsFd for ReadHalf {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        match self {
+            ReadHalf::Tcp(half) => half.as_ref().as_fd(),
+            ReadHalf::Unix(half) => half.as_ref().as_fd(),
+            ReadHalf::Encrypted(half) => half.get_ref().as_ref().as_fd(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsFd for WriteHalf {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        match self {
+            WriteHalf::Tcp(half) => half.as_ref().as_fd(),
+            WriteHalf::Unix(half) => half.as_ref().as_fd(),
+            WriteHalf::Encrypted(half) => half.get_ref().as_ref().as_fd(),
+        }
+    }
+}
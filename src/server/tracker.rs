@@ -0,0 +1,53 @@
+//! Keeps count of the connections being handled
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+#[derive(Default)]
+pub struct ConnectionTracker {
+    active: AtomicUsize,
+    notify: Notify,
+}
+
+/// Counts as an active connection until dropped
+pub struct TrackerGuard(Arc<ConnectionTracker>);
+
+impl Drop for TrackerGuard {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.notify.notify_waiters();
+        }
+    }
+}
+
+impl ConnectionTracker {
+    pub fn track(self: &Arc<Self>) -> TrackerGuard {
+        self.active.fetch_add(1, Ordering::AcqRel);
+        TrackerGuard(self.clone())
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// resolves once there are no more active connections
+    pub async fn drained(&self) {
+        loop {
+            // register interest before checking to not miss
+            // a notification sent in between
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.active() == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
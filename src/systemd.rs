@@ -0,0 +1,49 @@
+//! systemd socket activation, see sd_listen_fds(3)
+
+use std::{
+    io::{Error, ErrorKind},
+    net::TcpListener,
+    os::fd::{FromRawFd, RawFd},
+};
+
+/// first file descriptor passed by systemd
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// returns the listeners passed by systemd through `LISTEN_FDS`, if this
+/// process has been socket activated. Clears the environment, so it
+/// must run before any thread is spawned
+pub fn listeners() -> std::io::Result<Vec<TcpListener>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+
+    // children must not believe these sockets are meant for them
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(Vec::new());
+    };
+
+    if pid.parse() != Ok(std::process::id()) {
+        return Ok(Vec::new());
+    }
+
+    let fds: RawFd = fds
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid LISTEN_FDS"))?;
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds.max(0))
+        .map(|fd| {
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                return Err(Error::last_os_error());
+            }
+
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+            // fails if the socket isn't a TCP socket
+            listener.local_addr()?;
+            Ok(listener)
+        })
+        .collect()
+}
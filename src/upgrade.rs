@@ -0,0 +1,245 @@
+//! Zero-downtime binary upgrades. The running process spawns
+//! the new binary and hands its listening socket over through
+//! a unix socket with `SCM_RIGHTS`, then drains its connections
+
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    mem::size_of,
+    net::{SocketAddr, TcpListener},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{net::UnixStream, process::CommandExt},
+    },
+    path::PathBuf,
+    process::Command,
+    time::Duration,
+};
+
+/// environment variable containing the unix socket fd the
+/// listener is received from, set only on upgraded processes
+const UPGRADE_FD_ENV: &str = "HOPPER_UPGRADE_FD";
+
+/// time the new process has to read its configuration
+/// and take over the listener
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `cmsghdr` buffer with the alignment required by `CMSG_*`
+#[repr(C, align(8))]
+struct ControlBuffer([u8; 64]);
+
+fn os_error(result: isize) -> std::io::Result<usize> {
+    match result {
+        0.. => Ok(result as usize),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+fn send_fd(socket: &UnixStream, fd: RawFd) -> std::io::Result<()> {
+    let mut data = [0u8];
+    let mut control = ControlBuffer([0; 64]);
+
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as _,
+        iov_len: data.len(),
+    };
+
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.0.as_mut_ptr() as _;
+        msg.msg_controllen = libc::CMSG_SPACE(size_of::<RawFd>() as _) as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<RawFd>() as _) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+
+        os_error(libc::sendmsg(socket.as_raw_fd(), &msg, 0))?;
+    }
+
+    Ok(())
+}
+
+fn recv_fd(socket: &UnixStream) -> std::io::Result<RawFd> {
+    let mut data = [0u8];
+    let mut control = ControlBuffer([0; 64]);
+
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as _,
+        iov_len: data.len(),
+    };
+
+    let fd = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.0.as_mut_ptr() as _;
+        msg.msg_controllen = control.0.len() as _;
+
+        if os_error(libc::recvmsg(socket.as_raw_fd(), &mut msg, 0))? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "no file descriptor received",
+            ));
+        }
+
+        std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd)
+    };
+
+    // received descriptors must not leak into future upgrades
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(fd)
+}
+
+/// path of the binary currently running. On linux the path
+/// gets a " (deleted)" suffix when the binary is replaced
+fn current_binary() -> std::io::Result<PathBuf> {
+    let path = std::env::current_exe()?;
+
+    let path = match path.to_str().and_then(|p| p.strip_suffix(" (deleted)")) {
+        Some(stripped) => PathBuf::from(stripped),
+        None => path,
+    };
+
+    Ok(path)
+}
+
+/// spawns the binary found at the path of the current one, with the
+/// same arguments, and hands the `listeners` fds over to it. Returns once
+/// the new process is ready to accept connections
+pub fn handoff(listeners: &[OwnedFd]) -> std::io::Result<()> {
+    let (mut parent, child) = UnixStream::pair()?;
+    let child_fd = child.as_raw_fd();
+
+    let mut command = Command::new(current_binary()?);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(UPGRADE_FD_ENV, child_fd.to_string());
+
+    // std creates sockets with CLOEXEC, which must be cleared
+    // only for the new process to inherit the socket
+    unsafe {
+        command.pre_exec(move || match libc::fcntl(child_fd, libc::F_SETFD, 0) {
+            0.. => Ok(()),
+            _ => Err(Error::last_os_error()),
+        });
+    }
+
+    let mut process = command.spawn()?;
+    drop(child);
+
+    log::info!("Spawned new process {}", process.id());
+
+    let result = send_listeners(&mut parent, listeners);
+
+    // the new process must not keep serving with the listeners
+    // while this one goes on as if the upgrade never happened
+    if result.is_err() {
+        let _ = process.kill();
+        match process.wait() {
+            Ok(status) => log::warn!("New process {} exited: {status}", process.id()),
+            Err(err) => log::warn!("Cannot wait for new process {}: {err}", process.id()),
+        }
+    }
+
+    result
+}
+
+/// sends `listeners` over `parent`, then waits for the new process to
+/// confirm it took them over. Exiting before confirming closes the socket
+fn send_listeners(parent: &mut UnixStream, listeners: &[OwnedFd]) -> std::io::Result<()> {
+    parent.write_all(&(listeners.len() as u32).to_be_bytes())?;
+    for listener in listeners {
+        send_fd(parent, listener.as_raw_fd())?;
+    }
+
+    parent.set_read_timeout(Some(READY_TIMEOUT))?;
+    parent.read_exact(&mut [0])
+}
+
+/// Listeners handed over by the process that spawned this one
+pub struct Inherited {
+    listeners: Vec<TcpListener>,
+    parent: UnixStream,
+}
+
+impl Inherited {
+    /// receives the listeners if this process has been spawned by an
+    /// upgrade. Clears the environment, so it must run before any thread
+    pub fn receive() -> std::io::Result<Option<Self>> {
+        let Some(fd) = std::env::var_os(UPGRADE_FD_ENV) else {
+            return Ok(None);
+        };
+
+        std::env::remove_var(UPGRADE_FD_ENV);
+
+        let fd: RawFd = fd
+            .to_str()
+            .and_then(|fd| fd.parse().ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid upgrade fd"))?;
+
+        let mut parent = unsafe { UnixStream::from_raw_fd(fd) };
+
+        let mut count = [0; 4];
+        parent.read_exact(&mut count)?;
+
+        let listeners = (0..u32::from_be_bytes(count))
+            .map(|_| recv_fd(&parent).map(|fd| unsafe { TcpListener::from_raw_fd(fd) }))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(Self { listeners, parent }))
+    }
+
+    /// takes the inherited listeners bound to `address`
+    pub fn take(&mut self, address: SocketAddr) -> Vec<TcpListener> {
+        let (taken, rest) = std::mem::take(&mut self.listeners)
+            .into_iter()
+            .partition(|listener| listener.local_addr().ok() == Some(address));
+
+        self.listeners = rest;
+        taken
+    }
+
+    /// signals the parent process to stop accepting connections.
+    /// Listeners which haven't been taken are closed
+    pub fn take_over(mut self) -> std::io::Result<()> {
+        self.parent.write_all(&[1])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::TcpListener,
+        os::{fd::FromRawFd, unix::net::UnixStream},
+    };
+
+    use super::{recv_fd, send_fd};
+
+    #[test]
+    fn pass_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (a, b) = UnixStream::pair().unwrap();
+
+        send_fd(&a, std::os::fd::AsRawFd::as_raw_fd(&listener)).unwrap();
+        let received = unsafe { TcpListener::from_raw_fd(recv_fd(&b).unwrap()) };
+
+        assert_eq!(
+            received.local_addr().unwrap(),
+            listener.local_addr().unwrap()
+        );
+    }
+}